@@ -0,0 +1,86 @@
+//! Compares serde_json and simd-json on a published packages listing, the largest response the
+//! API gives. Run with `cargo bench --features simd`, adding `RUSTFLAGS="-C target-cpu=native"`
+//! to let simd-json use every instruction set the machine has.
+//!
+//! The listing is generated to match the shape of the real one (a package per county and kind
+//! of data, with Swedish names and descriptions), at a few times its size so timings are
+//! stable.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use lastkajen::types::{County, DataPackageFolder};
+
+const KINDS: &[&str] = &[
+    "GeoPackage",
+    "Shape",
+    "Inspire GML",
+    "Vägnät",
+    "Hastighetsgränser",
+    "Bärighet",
+    "Trafikflöden",
+    "Driftområden",
+];
+const COPIES: usize = 8;
+const MIN_TIME: Duration = Duration::from_secs(2);
+
+/// Number of packages, and the listing as JSON.
+fn catalog() -> (usize, Vec<u8>) {
+    let mut packages = Vec::new();
+    for copy in 0..COPIES {
+        for county in County::ALL {
+            for kind in KINDS {
+                let name = format!("{} {}", county, kind);
+                packages.push(serde_json::json!({
+                    "id": packages.len() + 1,
+                    "targetFolder": {
+                        "id": copy + 1,
+                        "name": "Länsfiler",
+                        "path": "Länsfiler NVDB-data",
+                    },
+                    "sourceFolder": format!("Datapaket\\Länsfiler NVDB-data\\{}\\{}", county, kind),
+                    "name": name,
+                    "description": format!(
+                        "Data från Nationell vägdatabas (NVDB) för {}, {}. Uppdateras varje natt \
+                         och levereras som zip-arkiv i koordinatsystemet SWEREF 99 TM.",
+                        county, kind
+                    ),
+                    "published": true,
+                }));
+            }
+        }
+    }
+
+    (packages.len(), serde_json::to_vec(&packages).unwrap())
+}
+
+/// Run `parse` for at least [`MIN_TIME`], returning the mean time per run.
+fn measure(mut parse: impl FnMut() -> Vec<DataPackageFolder>) -> Duration {
+    let started = Instant::now();
+    let mut runs = 0;
+    while started.elapsed() < MIN_TIME {
+        black_box(parse());
+        runs += 1;
+    }
+
+    started.elapsed() / runs
+}
+
+fn main() {
+    let (packages, body) = catalog();
+    let mb = body.len() as f64 / 1e6;
+    println!("{} packages, {:.2} MB", packages, mb);
+
+    let serde = measure(|| serde_json::from_slice(black_box(&body)).unwrap());
+    // Copied first, as the client does, simd-json parsing in place.
+    let simd = measure(|| simd_json::serde::from_slice(&mut black_box(&body).to_vec()).unwrap());
+
+    for (parser, mean) in [("serde_json", serde), ("simd-json", simd)] {
+        println!(
+            "{:<10} {:>10.3?} per listing, {:>7.1} MB/s",
+            parser,
+            mean,
+            mb / mean.as_secs_f64()
+        );
+    }
+}
@@ -1,10 +1,10 @@
 use std::error::Error;
-use std::fs::File;
 use std::{env, println};
 
 use dotenv::dotenv;
-use lastkajen::types::{DataPackageFolder, DownloadCategory};
-use lastkajen::Lastkajen;
+use lastkajen::catalog::find_package;
+use lastkajen::prelude::*;
+use lastkajen::types::FileFormat;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
@@ -20,30 +20,25 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // You'll need their IDs for further exploration.
     let packages = api.get_published_packages().await?;
 
-    // The API doesn't have any querying capabilities, so we have to do that ourselves.
-    let gavle_result: Vec<DataPackageFolder> = packages
-        .into_iter()
-        .filter(|package| package.source_folder == "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län")
-        .collect();
-    let gavle_result = gavle_result
-        .first()
-        .expect("Couldn't find the right package!");
+    // The API doesn't have any querying capabilities, so we have to do that ourselves. Finding
+    // the package this way fails if there are several matches, rather than picking one.
+    let gavle_result = find_package(&packages, |package| {
+        package.source_folder == "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län"
+    })?;
     // println!("{:?}", gavle_result);
 
-    let files = api.get_package_files(&gavle_result).await?;
+    let files = api.get_package_files(gavle_result).await?;
 
     // Most files outside the inspire folder have multiple file types
-    let gpkg: Vec<lastkajen::types::DataPackageFile> = files
-        .into_iter()
-        .filter(|file| file.name == "Gävleborgs_län_GeoPackage.zip")
-        .collect();
-    let gpkg = gpkg.first().expect("Couldn't find the right file!");
+    let gpkg = files
+        .iter()
+        .find(|file| file.format() == FileFormat::GeoPackage)
+        .expect("Couldn't find the right file!");
 
     println!("{:?}", gpkg);
 
     // Downloading a published package requires both the package ID and the file name.
-    // let mut file = File::open(&gpkg.name)?;
-    // api.download_file(DownloadCategory::Published { id: &gavle_result.id, file: &gpkg.name }, &mut file).await?;
+    // let path = api.download_package_file(gavle_result, gpkg, ".").await?;
 
     // ------------------------------- User 'orders' -------------------------------
     // Custom extracts from the national road database created via the Lastkajen website. The
@@ -55,7 +50,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         println!("{:?}", user_file);
 
         // An ID of any sort is not required for user files.
-        // let mut file = File::open(&user_file.name)?;
+        // let mut file = std::fs::File::create(&user_file.name)?;
         // api.download_file(DownloadCategory::User { file: &user_file.name }, &mut file).await?;
     } else {
         println!("No User files.");
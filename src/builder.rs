@@ -0,0 +1,537 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use reqwest::header::HeaderMap;
+
+use crate::http::{HttpClient, RequestHook};
+use crate::session::{Auth, LoginRequest};
+use crate::types::DataPackageFolder;
+use crate::{Lastkajen, LastkajenError, Result, DEFAULT_BASE_URL};
+
+// -----------------------------------------------------
+
+type RetryPredicate = dyn Fn(&LastkajenError) -> bool + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct RetryPolicy {
+    max_retries: u32,
+    backoff: Duration,
+    retry_if: Arc<RetryPredicate>,
+}
+
+impl RetryPolicy {
+    pub(crate) fn should_retry(&self, attempt: u32, err: &LastkajenError) -> bool {
+        attempt < self.max_retries && (self.retry_if)(err)
+    }
+
+    /// Exponential backoff, doubling the initial delay for each attempt.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(attempt))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff: Duration::from_millis(500),
+            retry_if: Arc::new(LastkajenError::is_retryable),
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("backoff", &self.backoff)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Which packages may be downloaded, everything by default.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Allowlist {
+    pub(crate) package_ids: Option<HashSet<usize>>,
+    pub(crate) source_folder_prefixes: Option<Vec<String>>,
+}
+
+impl Allowlist {
+    pub(crate) fn is_set(&self) -> bool {
+        self.package_ids.is_some() || self.source_folder_prefixes.is_some()
+    }
+}
+
+// -----------------------------------------------------
+/// Builder for a configured [`Lastkajen`] client.
+///
+/// ```rust,no_run
+/// # use tokio_test;
+/// # use std::env;
+/// # use std::time::Duration;
+/// # use lastkajen::*;
+/// # tokio_test::block_on(async {
+/// #   let username = env::var("USERNAME").unwrap();
+/// #   let password = env::var("PASSWORD").unwrap();
+///     let lastkajen = Lastkajen::builder()
+///         .max_retries(3)
+///         .backoff(Duration::from_secs(1))
+///         .retry_if(|err| matches!(err, LastkajenError::ApiError(code, _) if code.is_server_error()))
+///         .build(username, password)
+///         .await;
+///     assert!(lastkajen.is_ok());
+/// # })
+/// ```
+#[derive(Debug, Clone)]
+pub struct LastkajenBuilder {
+    base_url: String,
+    retry: RetryPolicy,
+    max_bytes_per_sec: Option<u64>,
+    max_json_bytes: Option<usize>,
+    max_download_bytes: Option<u64>,
+    decompress_json: bool,
+    staging_dir: Option<PathBuf>,
+    check_format: bool,
+    on_format_mismatch: Option<crate::util::FormatMismatchHook>,
+    allowlist: Allowlist,
+    login_retries: u32,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    default_headers: HeaderMap,
+    redirect_policy: Option<Arc<reqwest::redirect::Policy>>,
+    request_middleware: Option<RequestHook>,
+
+    #[cfg(all(
+        feature = "danger-tls",
+        any(
+            feature = "default-tls",
+            feature = "native-tls",
+            feature = "rustls-tls"
+        )
+    ))]
+    accept_invalid_certs: bool,
+
+    #[cfg(feature = "middleware")]
+    middleware: Option<reqwest_middleware::ClientWithMiddleware>,
+
+    #[cfg(feature = "time")]
+    clock: Arc<dyn crate::clock::Clock>,
+
+    #[cfg(feature = "timing")]
+    on_timing: Option<crate::timing::TimingHook>,
+}
+
+impl Default for LastkajenBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LastkajenBuilder {
+    pub fn new() -> Self {
+        Self {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            retry: RetryPolicy::default(),
+            max_bytes_per_sec: None,
+            max_json_bytes: None,
+            max_download_bytes: None,
+            decompress_json: true,
+            staging_dir: None,
+            check_format: false,
+            on_format_mismatch: None,
+            allowlist: Allowlist::default(),
+            login_retries: 3,
+            timeout: None,
+            connect_timeout: None,
+            default_headers: HeaderMap::new(),
+            redirect_policy: None,
+            request_middleware: None,
+
+            #[cfg(all(
+                feature = "danger-tls",
+                any(
+                    feature = "default-tls",
+                    feature = "native-tls",
+                    feature = "rustls-tls"
+                )
+            ))]
+            accept_invalid_certs: false,
+
+            #[cfg(feature = "middleware")]
+            middleware: None,
+
+            #[cfg(feature = "time")]
+            clock: Arc::new(crate::clock::SystemClock),
+
+            #[cfg(feature = "timing")]
+            on_timing: None,
+        }
+    }
+
+    /// Base url of the API, `https://lastkajen.trafikverket.se` by default.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Maximum number of times a failed request is retried, 0 (no retries) by default.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.retry.max_retries = max_retries;
+        self
+    }
+
+    /// Delay before the first retry, doubled for each following attempt.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.retry.backoff = backoff;
+        self
+    }
+
+    /// Decide which errors are worth retrying, replacing the default of retrying
+    /// [retryable](LastkajenError::is_retryable) errors.
+    ///
+    /// The predicate is only consulted for the idempotent GET operations (listings and download
+    /// tokens) and [`Lastkajen::download_file`], which mints a new token per attempt. Other
+    /// downloads, which expend a single use token, are never retried, and logins are retried
+    /// separately, see [`login_retries`](Self::login_retries).
+    pub fn retry_if<F>(mut self, retry_if: F) -> Self
+    where
+        F: Fn(&LastkajenError) -> bool + Send + Sync + 'static,
+    {
+        self.retry.retry_if = Arc::new(retry_if);
+        self
+    }
+
+    /// Maximum number of times logging in again for a new bearer token is retried on
+    /// [retryable](LastkajenError::is_retryable) errors, backing off like other requests. 3 by
+    /// default.
+    ///
+    /// The initial login in [`build`](Self::build) isn't retried.
+    pub fn login_retries(mut self, login_retries: u32) -> Self {
+        self.login_retries = login_retries;
+        self
+    }
+
+    /// Cap the average download bandwidth, unlimited by default.
+    pub fn max_bytes_per_sec(mut self, max_bytes_per_sec: u64) -> Self {
+        self.max_bytes_per_sec = Some(max_bytes_per_sec);
+        self
+    }
+
+    /// Cap how many bytes of a JSON response (listings, download tokens) are buffered, failing
+    /// with [`LastkajenError::ResponseTooLarge`] beyond it. Unlimited by default.
+    pub fn max_json_bytes(mut self, max_json_bytes: usize) -> Self {
+        self.max_json_bytes = Some(max_json_bytes);
+        self
+    }
+
+    /// Cap how many bytes a single download may transfer, failing with
+    /// [`LastkajenError::DownloadTooLarge`] beyond it. Unlimited by default.
+    ///
+    /// Checked against the announced `Content-Length` before anything is read and against the
+    /// bytes actually received, so a server sending more than it announced is cut off too. Data
+    /// beyond the cap is never written; what came before it may have been, except for
+    /// downloads to a path, which leave no file.
+    pub fn max_download_bytes(mut self, max_download_bytes: u64) -> Self {
+        self.max_download_bytes = Some(max_download_bytes);
+        self
+    }
+
+    /// Ask for JSON responses (listings, download tokens) gzip-compressed, and decompress them.
+    /// On by default. The `max_json_bytes` cap applies both before and after decompressing.
+    ///
+    /// This is transfer encoding, negotiated per request through `Accept-Encoding`, and never
+    /// applies to downloads: those are requested uncompressed, and a data file's own compression
+    /// (a `.zip`, say) is no transfer encoding, so it is always written exactly as served.
+    pub fn decompress_json_responses(mut self, decompress_json: bool) -> Self {
+        self.decompress_json = decompress_json;
+        self
+    }
+
+    /// Directory downloads to a path are written to before being moved into place, rather than
+    /// a `.part` file next to the target. `None`, the default, always stages next to the target.
+    /// Staged files are named after the target and a hash of its full path, so downloads of
+    /// same-named files to different directories don't collide.
+    ///
+    /// Moving is only atomic within a filesystem, so on Unix the directory is only used for
+    /// targets on the same filesystem as it, falling back to a `.part` file for others.
+    /// Elsewhere that can't be checked, and a download staged on another filesystem is copied to
+    /// a `.part` file next to the target before being renamed into place.
+    pub fn staging_dir(mut self, staging_dir: Option<PathBuf>) -> Self {
+        self.staging_dir = staging_dir;
+        self
+    }
+
+    /// Check that downloads to a path look like what their file extension says, e.g. that a
+    /// `.zip` really is a ZIP archive rather than an error page, see
+    /// [`detect_format`](crate::util::detect_format). A mismatch fails the download with
+    /// [`LastkajenError::FormatMismatch`], leaving no file. Off by default.
+    ///
+    /// To be warned about mismatches without failing downloads, use
+    /// [`LastkajenBuilder::on_format_mismatch`] instead.
+    pub fn check_format(mut self, check_format: bool) -> Self {
+        self.check_format = check_format;
+        self
+    }
+
+    /// Check downloads to a path like [`LastkajenBuilder::check_format`] does, calling
+    /// `on_format_mismatch` with the path and the expected and found formats for any that don't
+    /// match. Unless `check_format` is set as well, the download is kept and succeeds.
+    pub fn on_format_mismatch<F>(mut self, on_format_mismatch: F) -> Self
+    where
+        F: Fn(&std::path::Path, crate::util::DetectedFormat, crate::util::DetectedFormat)
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.on_format_mismatch = Some(crate::util::FormatMismatchHook(Arc::new(
+            on_format_mismatch,
+        )));
+        self
+    }
+
+    /// Only allow downloading from the packages with these ids, e.g. to limit what users of a
+    /// service built on the client can reach. Anything else fails with
+    /// [`LastkajenError::NotAllowed`] before any request is made.
+    ///
+    /// Once this or [`allowed_source_folder_prefixes`](Self::allowed_source_folder_prefixes) is
+    /// set, user files are refused too, belonging to no package, as are
+    /// [`download_href`](Lastkajen::download_href) links, which can't be told apart.
+    pub fn allowed_package_ids(mut self, package_ids: HashSet<usize>) -> Self {
+        self.allowlist.package_ids = Some(package_ids);
+        self
+    }
+
+    /// Only allow downloading from packages whose source folder is one of these folders or lies
+    /// under it, e.g. `Datapaket\Länsfiler NVDB-data\Gävleborgs län`. Whole folder names are
+    /// matched, so `Datapaket\Trafik` doesn't allow `Datapaket\Trafikflöden`. Otherwise as
+    /// [`allowed_package_ids`](Self::allowed_package_ids), which a package must then pass as
+    /// well.
+    ///
+    /// Downloads only know their package's id, so its source folder is looked up in the
+    /// published packages. The listing is kept, and only fetched again for a package it doesn't
+    /// have. No download token is minted for refused packages.
+    pub fn allowed_source_folder_prefixes(mut self, prefixes: Vec<String>) -> Self {
+        self.allowlist.source_folder_prefixes = Some(prefixes);
+        self
+    }
+
+    /// Timeout for entire requests, from connecting until the body has been read. Keep in mind
+    /// that this includes downloads, which may take a while. No timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Timeout for establishing a connection only, letting unreachable hosts fail fast without
+    /// limiting slow transfers. No timeout by default.
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Headers sent with every request, e.g. a custom `User-Agent` or `Accept-Language: sv-SE`.
+    pub fn default_headers(mut self, headers: HeaderMap) -> Self {
+        self.default_headers = headers;
+        self
+    }
+
+    /// How redirects are followed, up to 10 by default. Download urls carry their token in the
+    /// query, so [`Policy::none`](reqwest::redirect::Policy::none) helps auditing where it's
+    /// sent.
+    pub fn redirect_policy(mut self, redirect_policy: reqwest::redirect::Policy) -> Self {
+        self.redirect_policy = Some(Arc::new(redirect_policy));
+        self
+    }
+
+    /// **Dangerous:** accept any TLS certificate, including self-signed, expired and ones for
+    /// other hosts, e.g. to test against a local HTTPS mock.
+    ///
+    /// This defeats TLS entirely: anyone on the network path can read the credentials and
+    /// tokens sent. Never enable it in production. Only available with the `danger-tls`
+    /// feature and a TLS backend.
+    #[cfg(all(
+        feature = "danger-tls",
+        any(
+            feature = "default-tls",
+            feature = "native-tls",
+            feature = "rustls-tls"
+        )
+    ))]
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Inspect or modify every request, including logins and downloads, right before it is sent
+    /// and after authentication has been attached, e.g. for audit logging or request signing.
+    ///
+    /// Retried requests pass through it again.
+    pub fn request_middleware<F>(mut self, request_middleware: F) -> Self
+    where
+        F: Fn(reqwest::Request) -> reqwest::Request + Send + Sync + 'static,
+    {
+        self.request_middleware = Some(RequestHook(Arc::new(request_middleware)));
+        self
+    }
+
+    /// Send every request, including logins and downloads, through an existing middleware
+    /// client, e.g. for shared tracing or caching.
+    ///
+    /// The middleware client's own configuration applies, so [`timeout`](Self::timeout),
+    /// [`connect_timeout`](Self::connect_timeout), [`default_headers`](Self::default_headers),
+    /// [`redirect_policy`](Self::redirect_policy) and accepting invalid certificates are
+    /// ignored. Requests are still retried according to [`max_retries`](Self::max_retries),
+    /// which is 0 by default, to leave retrying to the middleware.
+    #[cfg(feature = "middleware")]
+    pub fn middleware(mut self, middleware: reqwest_middleware::ClientWithMiddleware) -> Self {
+        self.middleware = Some(middleware);
+        self
+    }
+
+    /// Time source for token expiry and refresh, the system clock by default. Mostly useful in
+    /// tests.
+    #[cfg(feature = "time")]
+    pub fn clock(mut self, clock: impl crate::clock::Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Receive a [`RequestTiming`](crate::timing::RequestTiming) for every API request that got
+    /// a response. Downloads aren't timed.
+    #[cfg(feature = "timing")]
+    pub fn on_timing<F>(mut self, on_timing: F) -> Self
+    where
+        F: Fn(&crate::timing::RequestTiming) + Send + Sync + 'static,
+    {
+        self.on_timing = Some(crate::timing::TimingHook(Arc::new(on_timing)));
+        self
+    }
+
+    /// Create the Lastkajen instance, fetching a bearer token.
+    pub async fn build(self, user_name: String, password: String) -> Result<Lastkajen> {
+        self.build_with_login(LoginRequest::new(user_name, password))
+            .await
+    }
+
+    /// Like [`build`](Self::build), also fetching the published packages right away over the
+    /// login's connection.
+    pub async fn build_with_catalog(
+        self,
+        user_name: String,
+        password: String,
+    ) -> Result<(Lastkajen, Vec<DataPackageFolder>)> {
+        let lastkajen = self.build(user_name, password).await?;
+        let packages = lastkajen.get_published_packages().await?;
+
+        Ok((lastkajen, packages))
+    }
+
+    /// Like [`build`](Self::build), logging in with extra form parameters, see
+    /// [`LoginRequest`].
+    pub async fn build_with_login(self, login: LoginRequest) -> Result<Lastkajen> {
+        let client = self.build_client(false)?;
+        let logged_in = Lastkajen::login(&client, &self.base_url, &login).await?;
+
+        #[cfg(feature = "time")]
+        let auth = logged_in.into_auth(self.clock.now_utc());
+        #[cfg(not(feature = "time"))]
+        let auth = Auth::bearer(logged_in.token);
+
+        Ok(self.finish(client, auth, Some(login)))
+    }
+
+    /// Create the Lastkajen instance, authenticating with an API key header instead of a
+    /// bearer token.
+    ///
+    /// Unlike `Authorization`, the key header would be kept on redirects to other hosts, so
+    /// those aren't followed and come back as [`LastkajenError::ApiError`] with the redirect's
+    /// status. Same-origin redirects are followed according to
+    /// [`redirect_policy`](Self::redirect_policy).
+    pub fn build_with_api_key(self, key: String) -> Result<Lastkajen> {
+        let client = self.build_client(true)?;
+
+        Ok(self.finish(client, Auth::ApiKey(key), None))
+    }
+
+    /// Build the HTTP client, only following same-origin redirects if `same_origin_redirects`
+    /// is set.
+    fn build_client(&self, same_origin_redirects: bool) -> Result<HttpClient> {
+        #[cfg(feature = "middleware")]
+        if let Some(middleware) = &self.middleware {
+            return Ok(HttpClient::with_middleware(middleware.clone())
+                .on_request(self.request_middleware.clone()));
+        }
+
+        let mut client = reqwest::Client::builder().default_headers(self.default_headers.clone());
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            client = client.connect_timeout(connect_timeout);
+        }
+        if same_origin_redirects || self.redirect_policy.is_some() {
+            // Policies can't be cloned, so share the configured one with every built client.
+            let redirect_policy = self.redirect_policy.clone().unwrap_or_default();
+            client = client.redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                let other_origin = attempt
+                    .previous()
+                    .first()
+                    .is_some_and(|first| first.origin() != attempt.url().origin());
+                if same_origin_redirects && other_origin {
+                    return attempt.stop();
+                }
+                redirect_policy.redirect(attempt)
+            }));
+        }
+
+        #[cfg(all(
+            feature = "danger-tls",
+            any(
+                feature = "default-tls",
+                feature = "native-tls",
+                feature = "rustls-tls"
+            )
+        ))]
+        if self.accept_invalid_certs {
+            client = client.danger_accept_invalid_certs(true);
+        }
+
+        Ok(HttpClient::new(client.build()?).on_request(self.request_middleware.clone()))
+    }
+
+    fn finish(
+        self,
+        client: HttpClient,
+        auth: Auth,
+        credentials: Option<LoginRequest>,
+    ) -> Lastkajen {
+        Lastkajen {
+            auth: RwLock::new(auth),
+            credentials,
+            refresh_lock: tokio::sync::Mutex::new(None),
+            login_retries: self.login_retries,
+            client,
+            base_url: self.base_url,
+            retry: self.retry,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            max_json_bytes: self.max_json_bytes,
+            max_download_bytes: self.max_download_bytes,
+            decompress_json: self.decompress_json,
+            staging_dir: self.staging_dir,
+            check_format: self.check_format,
+            on_format_mismatch: self.on_format_mismatch,
+            allowlist: self.allowlist,
+            package_files_cache: Mutex::default(),
+            source_folders: Mutex::default(),
+
+            #[cfg(feature = "time")]
+            clock: self.clock,
+
+            #[cfg(feature = "timing")]
+            on_timing: self.on_timing,
+        }
+    }
+}
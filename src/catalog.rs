@@ -0,0 +1,339 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use futures::{Stream, TryStreamExt};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::types::{DataPackageFile, DataPackageFolder, PackageId};
+use crate::{Lastkajen, LastkajenError, Result};
+
+// -----------------------------------------------------
+
+/// A package present in both listings, but with different contents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangedPackage {
+    pub old: DataPackageFolder,
+    pub new: DataPackageFolder,
+}
+
+/// Differences between two package listings, see [`diff_packages`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PackageDiff {
+    pub added: Vec<DataPackageFolder>,
+    pub removed: Vec<DataPackageFolder>,
+    pub changed: Vec<ChangedPackage>,
+}
+
+impl PackageDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two package listings by id, e.g. from consecutive
+/// [`Lastkajen::get_published_packages`](crate::Lastkajen::get_published_packages) calls.
+///
+/// Results keep the order of `new` (added, changed) and `old` (removed).
+pub fn diff_packages(old: &[DataPackageFolder], new: &[DataPackageFolder]) -> PackageDiff {
+    let old_by_id: HashMap<usize, &DataPackageFolder> =
+        old.iter().map(|package| (package.id, package)).collect();
+    let new_by_id: HashMap<usize, &DataPackageFolder> =
+        new.iter().map(|package| (package.id, package)).collect();
+
+    let mut diff = PackageDiff::default();
+    for package in new {
+        match old_by_id.get(&package.id) {
+            None => diff.added.push(package.clone()),
+            Some(old) if *old != package => diff.changed.push(ChangedPackage {
+                old: (*old).clone(),
+                new: package.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+
+    diff.removed = old
+        .iter()
+        .filter(|package| !new_by_id.contains_key(&package.id))
+        .cloned()
+        .collect();
+
+    diff
+}
+
+/// The one package matching `predicate`, failing with [`LastkajenError::NotFound`] if none do
+/// and [`LastkajenError::Ambiguous`] if several do, rather than settling for an arbitrary match.
+pub fn find_package<P>(
+    packages: &[DataPackageFolder],
+    mut predicate: P,
+) -> Result<&DataPackageFolder>
+where
+    P: FnMut(&DataPackageFolder) -> bool,
+{
+    let mut matches = packages.iter().filter(|package| predicate(package));
+
+    let found = matches
+        .next()
+        .ok_or_else(|| LastkajenError::NotFound("Matching data package".to_string()))?;
+    let others = matches.count();
+    if others > 0 {
+        return Err(LastkajenError::Ambiguous(format!(
+            "{} data packages match",
+            others + 1
+        )));
+    }
+
+    Ok(found)
+}
+
+// -----------------------------------------------------
+
+/// A package listing along with when it was fetched, for persisting between runs. See
+/// [`Lastkajen::get_published_packages_cached`].
+///
+/// The default cache is empty and always stale.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CatalogCache {
+    pub packages: Vec<DataPackageFolder>,
+    pub fetched_at: SystemTime,
+}
+
+impl Default for CatalogCache {
+    fn default() -> Self {
+        Self {
+            packages: Vec::new(),
+            fetched_at: SystemTime::UNIX_EPOCH,
+        }
+    }
+}
+
+impl CatalogCache {
+    /// Read a cache previously written by [`CatalogCache::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read(path)?;
+
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    /// Whether the listing was fetched more than `ttl` ago. A `fetched_at` in the future counts
+    /// as fresh.
+    pub fn is_stale(&self, ttl: Duration) -> bool {
+        self.fetched_at.elapsed().is_ok_and(|age| age > ttl)
+    }
+}
+
+impl Lastkajen {
+    /// Get the published package with the given source folder, e.g.
+    /// `Datapaket\Länsfiler NVDB-data\Gävleborgs län`. See [`find_package`].
+    pub async fn find_package_by_source_folder(
+        &self,
+        source_folder: &str,
+    ) -> Result<DataPackageFolder> {
+        let packages = self.get_published_packages().await?;
+
+        find_package(&packages, |package| package.source_folder == source_folder).cloned()
+    }
+
+    /// Get available public data packages from `cache`, refetching them into it first if it is
+    /// older than `ttl`.
+    pub async fn get_published_packages_cached<'a>(
+        &self,
+        cache: &'a mut CatalogCache,
+        ttl: Duration,
+    ) -> Result<&'a [DataPackageFolder]> {
+        if cache.is_stale(ttl) {
+            let fetched_at = SystemTime::now();
+            cache.packages = self.get_published_packages().await?;
+            cache.fetched_at = fetched_at;
+        }
+
+        Ok(&cache.packages)
+    }
+}
+
+// -----------------------------------------------------
+
+/// Which packages a catalog walk has finished with, for resuming it after an interruption. See
+/// [`Lastkajen::all_downloadable_files_from`].
+///
+/// Clones share their state, so a clone kept aside follows the walk the original was handed
+/// to, and can be saved as it goes.
+#[derive(Debug, Clone, Default)]
+pub struct CatalogCursor {
+    completed: Arc<Mutex<BTreeSet<PackageId>>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CursorState {
+    completed: BTreeSet<PackageId>,
+}
+
+impl CatalogCursor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a cursor previously written by [`CatalogCursor::save`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let contents = std::fs::read(path)?;
+
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec(self)?)?;
+
+        Ok(())
+    }
+
+    pub fn is_completed(&self, package_id: PackageId) -> bool {
+        self.lock().contains(&package_id)
+    }
+
+    /// Ids of the completed packages, in ascending order.
+    pub fn completed(&self) -> Vec<PackageId> {
+        self.lock().iter().copied().collect()
+    }
+
+    /// Mark a package as done, e.g. for one handled outside of the walk.
+    pub fn complete(&self, package_id: PackageId) {
+        self.lock().insert(package_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeSet<PackageId>> {
+        self.completed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Serialize for CatalogCursor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        CursorState {
+            completed: self.lock().clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CatalogCursor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let state = CursorState::deserialize(deserializer)?;
+
+        Ok(Self {
+            completed: Arc::new(Mutex::new(state.completed)),
+        })
+    }
+}
+
+impl Lastkajen {
+    /// Like [`Lastkajen::all_downloadable_files`], skipping the packages `cursor` has completed
+    /// and completing packages as the walk goes.
+    ///
+    /// A package counts as completed once the stream is polled past its last file, i.e. when
+    /// the caller is done with all of them. Keep a clone of the cursor and save it along the
+    /// way, and an interrupted walk is restarted from the package it was in the middle of.
+    /// Files of a package are never interleaved with another's, and packages without files
+    /// are completed as soon as they're listed.
+    pub fn all_downloadable_files_from(
+        &self,
+        cursor: CatalogCursor,
+    ) -> impl Stream<Item = Result<(PackageId, DataPackageFile)>> + '_ {
+        let skip = cursor.clone();
+
+        self.downloadable_files_by_package(move |id| !skip.is_completed(id))
+            .try_filter_map(move |(id, file)| {
+                if file.is_none() {
+                    cursor.complete(id);
+                }
+                futures::future::ok(file.map(|file| (id, file)))
+            })
+    }
+}
+
+// -----------------------------------------------------
+
+/// Summary of the published catalog, see [`Lastkajen::catalog_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CatalogStats {
+    pub packages: usize,
+    /// Number of packages per county, for packages with a county.
+    pub packages_by_county: BTreeMap<String, usize>,
+    /// Number of (non-folder) files across all packages.
+    pub files: usize,
+    /// Sum of the file sizes which could be parsed.
+    pub total_bytes: u64,
+    /// Number of files whose size couldn't be parsed, and so aren't part of `total_bytes`.
+    pub unknown_sizes: usize,
+}
+
+impl Lastkajen {
+    /// Count the published packages and their files, summing up file sizes.
+    ///
+    /// Like [`Lastkajen::search_files`], every published package's file listing is fetched.
+    pub async fn catalog_stats(&self) -> Result<CatalogStats> {
+        let packages = self.get_published_packages().await?;
+        let packages = self.get_files_of_packages(packages).await?;
+
+        let mut stats = CatalogStats {
+            packages: packages.len(),
+            ..CatalogStats::default()
+        };
+        for (package, files) in &packages {
+            if let Some(county) = package.county_name() {
+                *stats
+                    .packages_by_county
+                    .entry(county.to_string())
+                    .or_default() += 1;
+            }
+
+            for file in files.iter().filter(|file| !file.is_folder) {
+                stats.files += 1;
+                match file.size_bytes() {
+                    Some(bytes) => stats.total_bytes += bytes,
+                    None => stats.unknown_sizes += 1,
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+// -----------------------------------------------------
+
+#[cfg(feature = "csv")]
+impl Lastkajen {
+    /// Write the published packages as CSV, with the columns `id`, `name`, `source_folder`,
+    /// `county` and `published`.
+    pub async fn export_catalog_csv(&self, writer: impl std::io::Write) -> Result<()> {
+        let packages = self.get_published_packages().await?;
+
+        let mut csv = csv::Writer::from_writer(writer);
+        let mut write = || -> csv::Result<()> {
+            csv.write_record(["id", "name", "source_folder", "county", "published"])?;
+            for package in &packages {
+                csv.write_record([
+                    package.id.to_string().as_str(),
+                    &package.name,
+                    &package.source_folder,
+                    package.county_name().unwrap_or_default(),
+                    if package.published { "true" } else { "false" },
+                ])?;
+            }
+            csv.flush()?;
+            Ok(())
+        };
+
+        write().map_err(std::io::Error::from)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+use std::fmt;
+
+/// Source of the current time for token expiry and refresh, see
+/// [`LastkajenBuilder::clock`](crate::LastkajenBuilder::clock).
+///
+/// Replace it in tests to control when tokens expire:
+///
+/// ```rust
+/// # use lastkajen::clock::Clock;
+/// #[derive(Debug)]
+/// struct Frozen(time::OffsetDateTime);
+///
+/// impl Clock for Frozen {
+///     fn now_utc(&self) -> time::OffsetDateTime {
+///         self.0
+///     }
+/// }
+/// ```
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now_utc(&self) -> time::OffsetDateTime;
+}
+
+/// The system clock, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_utc(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::now_utc()
+    }
+}
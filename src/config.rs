@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{Lastkajen, LastkajenError, Result};
+
+// -----------------------------------------------------
+
+/// Credentials file read by [`Lastkajen::from_config`]. Deliberately not `Debug`, so the
+/// password can't end up in logs.
+#[derive(Deserialize)]
+struct Config {
+    username: String,
+    password: String,
+    #[serde(default)]
+    base_url: Option<String>,
+}
+
+impl Lastkajen {
+    /// Create new Lastkajen instance from a JSON credentials file, e.g. kept in a dotfile:
+    ///
+    /// ```json
+    /// { "username": "...", "password": "...", "base_url": "https://lastkajen.trafikverket.se" }
+    /// ```
+    ///
+    /// `base_url` is optional. On unix the file is refused if it's readable or writable by
+    /// anyone but its owner, as it contains a password. Errors never include file contents.
+    pub async fn from_config(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        check_permissions(path)?;
+
+        let contents = fs::read(path)?;
+        let config: Config = serde_json::from_slice(&contents).map_err(|err| {
+            // serde_json errors may quote the offending value, which could be the password.
+            LastkajenError::LastkajenError(format!(
+                "Invalid config file {}: line {}, column {}",
+                path.display(),
+                err.line(),
+                err.column()
+            ))
+        })?;
+
+        let mut builder = Lastkajen::builder();
+        if let Some(base_url) = config.base_url {
+            builder = builder.base_url(base_url);
+        }
+
+        builder.build(config.username, config.password).await
+    }
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(LastkajenError::LastkajenError(format!(
+            "Config file {} is accessible by other users (mode {:o}), restrict it with `chmod 600`",
+            path.display(),
+            mode & 0o777
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::sync::Arc;
+
+use crate::Result;
+
+/// Callback given every request right before it is sent, see
+/// [`LastkajenBuilder::request_middleware`](crate::LastkajenBuilder::request_middleware).
+#[derive(Clone)]
+pub(crate) struct RequestHook(
+    pub(crate) Arc<dyn Fn(reqwest::Request) -> reqwest::Request + Send + Sync>,
+);
+
+impl fmt::Debug for RequestHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RequestHook")
+    }
+}
+
+/// HTTP client every request is sent through, optionally wrapped in middleware. Everything shares
+/// its connection pool.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    on_request: Option<RequestHook>,
+
+    #[cfg(feature = "middleware")]
+    middleware: Option<reqwest_middleware::ClientWithMiddleware>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            on_request: None,
+
+            #[cfg(feature = "middleware")]
+            middleware: None,
+        }
+    }
+
+    /// Send requests through `middleware` instead. Requests are still built with `client`,
+    /// which is of no consequence as building them doesn't touch the client's configuration.
+    #[cfg(feature = "middleware")]
+    pub(crate) fn with_middleware(middleware: reqwest_middleware::ClientWithMiddleware) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            on_request: None,
+            middleware: Some(middleware),
+        }
+    }
+
+    pub(crate) fn on_request(mut self, on_request: Option<RequestHook>) -> Self {
+        self.on_request = on_request;
+        self
+    }
+
+    pub(crate) fn get(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub(crate) fn post(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub(crate) async fn execute(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let request = match &self.on_request {
+            Some(on_request) => (on_request.0)(request),
+            None => request,
+        };
+
+        #[cfg(feature = "otel")]
+        return crate::otel::traced(request, |request| self.send(request)).await;
+
+        #[cfg(not(feature = "otel"))]
+        self.send(request).await
+    }
+
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        #[cfg(feature = "middleware")]
+        if let Some(middleware) = &self.middleware {
+            return Ok(middleware.execute(request).await?);
+        }
+
+        Ok(self.client.execute(request).await?)
+    }
+}
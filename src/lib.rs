@@ -1,7 +1,77 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::io::Write;
+use std::fs;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
 
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING};
+use reqwest::StatusCode;
+
+pub mod builder;
+pub mod catalog;
+#[cfg(feature = "time")]
+pub mod clock;
+#[cfg(feature = "config")]
+mod config;
+mod http;
+#[cfg(feature = "otel")]
+mod otel;
+pub mod progress;
+mod session;
+pub mod sink;
+pub mod sync;
+mod throttle;
+#[cfg(feature = "timing")]
+pub mod timing;
 pub mod types;
+pub mod util;
+
+/// The client and the types most uses need, for `use lastkajen::prelude::*;`.
+///
+/// Leaves out [`Result`] so as not to shadow the standard one.
+pub mod prelude {
+    pub use crate::progress::DownloadProgress;
+    pub use crate::types::{
+        DataPackageFile, DataPackageFolder, DownloadCategory, DownloadToken, FileSize,
+        TargetFolder, Token, UserFile,
+    };
+    pub use crate::{Lastkajen, LastkajenBuilder, LastkajenError};
+}
+
+pub use builder::LastkajenBuilder;
+use session::Auth;
+#[cfg(feature = "time")]
+pub use session::RefreshHandle;
+pub use session::{LoginRequest, Session};
+
+pub(crate) const DEFAULT_BASE_URL: &str = "https://lastkajen.trafikverket.se";
+
+/// Header carrying the key for API key authentication.
+const API_KEY_HEADER: &str = "Ocp-Apim-Subscription-Key";
+
+const PUBLISHED_PACKAGES_PATH: &str = "/api/DataPackage/GetPublishedDataPackages";
+const USER_FILES_PATH: &str = "/api/file/GetUserFiles";
+
+/// Successful statuses of the GET API endpoints (listings, download tokens). Some listings
+/// answer an empty result with a 204.
+const API_STATUSES: &[StatusCode] = &[StatusCode::OK, StatusCode::NO_CONTENT];
+const LOGIN_STATUSES: &[StatusCode] = &[StatusCode::OK];
+const DOWNLOAD_STATUSES: &[StatusCode] = &[StatusCode::OK];
+/// Whether a server honours a `Range` header is only known from its answer.
+const RANGE_STATUSES: &[StatusCode] = &[StatusCode::OK, StatusCode::PARTIAL_CONTENT];
+/// Downloads are always asked for as is, so no transfer encoding can touch the file's bytes,
+/// whatever default headers the client was given.
+const IDENTITY: HeaderValue = HeaderValue::from_static("identity");
+
+/// Maximum number of package file listings fetched at once.
+const PACKAGE_FILES_CONCURRENCY: usize = 8;
+
+fn package_files_path(id: &usize) -> String {
+    format!("/api/DataPackage/GetDataPackageFiles/{}", id)
+}
 
 // -----------------------------------------------------
 // See https://lastkajen2-p.ea.trafikverket.se/assets/Lastkajen2_API_Information.pdf for more
@@ -11,10 +81,70 @@ pub mod types;
 pub enum LastkajenError {
     ReqwestError(reqwest::Error),
     IoError(std::io::Error),
+    JsonError(serde_json::Error),
     StatusError(reqwest::StatusCode),
+    ApiError(reqwest::StatusCode, String),
+    NotFound(String),
+    /// More than one item matched where exactly one was expected.
+    Ambiguous(String),
+    EmptyResponse,
+    /// Not enough free disk space for a download, in bytes needed and available.
+    InsufficientSpace(u64, u64),
+    /// The API answered with a maintenance page rather than JSON. Usually temporary.
+    Maintenance,
+    /// A JSON response exceeded the configured maximum number of bytes.
+    ResponseTooLarge(usize),
+    /// The server answered a ranged download with the whole file (or another range), see
+    /// [`Lastkajen::download_range`].
+    RangeNotSupported,
+    /// A download exceeded the configured maximum number of bytes, see
+    /// [`LastkajenBuilder::max_download_bytes`].
+    DownloadTooLarge(u64),
+    /// A download's content didn't match the format its file extension implied, as expected
+    /// and found. See [`LastkajenBuilder::check_format`].
+    FormatMismatch(util::DetectedFormat, util::DetectedFormat),
+    /// A download was refused by the client's allowlist, see
+    /// [`LastkajenBuilder::allowed_package_ids`].
+    NotAllowed(String),
+    /// Writing to one of several sinks failed, by its index, see [`Lastkajen::download_tee`].
+    SinkFailed(usize, std::io::Error),
+    /// Logging in again for a new bearer token failed, after any retries. Shared by every
+    /// request that waited on the refresh.
+    RefreshFailed(Arc<LastkajenError>),
+    /// The account's download quota is used up, with how long the API asked to wait (from a
+    /// `Retry-After` header) if it said.
+    QuotaExceeded {
+        retry_after: Option<Duration>,
+    },
     LastkajenError(String),
 }
 
+impl LastkajenError {
+    /// Whether retrying the same request after a short backoff may succeed: connection errors,
+    /// timeouts, 5xx and 429 responses. This is what the client retries by default, see
+    /// [`LastkajenBuilder::retry_if`].
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::ReqwestError(err) => err.is_connect() || err.is_timeout(),
+            Self::StatusError(code) | Self::ApiError(code, _) => {
+                code.is_server_error() || *code == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the error is down to a temporary condition, even if not one worth retrying
+    /// right away: anything [retryable](Self::is_retryable), maintenance, an exceeded quota and
+    /// failed refreshes for such reasons.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Maintenance | Self::QuotaExceeded { .. } => true,
+            Self::RefreshFailed(err) => err.is_transient(),
+            err => err.is_retryable(),
+        }
+    }
+}
+
 impl From<reqwest::Error> for LastkajenError {
     fn from(error: reqwest::Error) -> Self {
         Self::ReqwestError(error)
@@ -27,17 +157,78 @@ impl From<std::io::Error> for LastkajenError {
     }
 }
 
+impl From<serde_json::Error> for LastkajenError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::JsonError(error)
+    }
+}
+
 impl From<reqwest::StatusCode> for LastkajenError {
     fn from(status: reqwest::StatusCode) -> Self {
         Self::StatusError(status)
     }
 }
 
+#[cfg(feature = "middleware")]
+impl From<reqwest_middleware::Error> for LastkajenError {
+    fn from(error: reqwest_middleware::Error) -> Self {
+        match error {
+            reqwest_middleware::Error::Reqwest(err) => Self::ReqwestError(err),
+            reqwest_middleware::Error::Middleware(err) => {
+                Self::LastkajenError(format!("middleware error: {}", err))
+            }
+        }
+    }
+}
+
 impl fmt::Display for LastkajenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::ReqwestError(err) => write!(f, "reqwest::Error: {}", err),
             Self::StatusError(code) => write!(f, "Api Request Error: HTTP status {}", code),
+            Self::ApiError(code, text) => {
+                write!(f, "Api Request Error: HTTP status {}: {}", code, text)
+            }
+            Self::NotFound(what) => write!(f, "Not Found: {}", what),
+            Self::Ambiguous(what) => write!(f, "Ambiguous: {}", what),
+            Self::EmptyResponse => write!(f, "Api Request Error: empty response body"),
+            Self::InsufficientSpace(needed, available) => write!(
+                f,
+                "Insufficient Space: {} bytes needed, {} available",
+                needed, available
+            ),
+            Self::Maintenance => write!(f, "Api Request Error: service under maintenance"),
+            Self::ResponseTooLarge(limit) => {
+                write!(f, "Api Request Error: response larger than {} bytes", limit)
+            }
+            Self::RangeNotSupported => {
+                write!(
+                    f,
+                    "Download Error: server doesn't support the requested range"
+                )
+            }
+            Self::DownloadTooLarge(limit) => {
+                write!(f, "Download Error: download larger than {} bytes", limit)
+            }
+            Self::QuotaExceeded {
+                retry_after: Some(retry_after),
+            } => write!(
+                f,
+                "Api Request Error: quota exceeded, retry after {}s",
+                retry_after.as_secs()
+            ),
+            Self::QuotaExceeded { retry_after: None } => {
+                write!(f, "Api Request Error: quota exceeded")
+            }
+            Self::FormatMismatch(expected, found) => write!(
+                f,
+                "Format Mismatch: expected {} content, found {}",
+                expected, found
+            ),
+            Self::NotAllowed(what) => write!(f, "Not Allowed: {}", what),
+            Self::SinkFailed(index, err) => write!(f, "IO Error: sink {}: {}", index, err),
+            Self::RefreshFailed(err) => write!(f, "Refresh Error: {}", err),
+            Self::JsonError(err) => write!(f, "serde_json::Error: {}", err),
             Self::IoError(err) => write!(f, "IO Error: {}", err),
             Self::LastkajenError(err) => write!(f, "Lastkajen Error: {}", err), // Add formatting for other error variants
         }
@@ -46,12 +237,35 @@ impl fmt::Display for LastkajenError {
 
 impl std::error::Error for LastkajenError {}
 
+impl From<LastkajenError> for std::io::Error {
+    fn from(error: LastkajenError) -> Self {
+        match error {
+            LastkajenError::IoError(err) => err,
+            err => std::io::Error::other(err),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, LastkajenError>;
 
+/// Parser entry points for `cargo fuzz`, see `fuzz/`. Not part of the public API.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    pub fn parse_size(text: &str) -> Option<u64> {
+        crate::types::parse_size(text)
+    }
+
+    #[cfg(feature = "time")]
+    pub fn parse_date_time(text: &str) -> Option<time::OffsetDateTime> {
+        crate::types::parse_date_time(text)
+    }
+}
+
 // -----------------------------------------------------
 /// Api client for Lastkajen.
 ///
-/// ```rust
+/// ```rust,no_run
 /// # use tokio_test;
 /// # use std::env;
 /// # use dotenv::dotenv;
@@ -70,18 +284,53 @@ pub type Result<T> = std::result::Result<T, LastkajenError>;
 ///
 #[derive(Debug)]
 pub struct Lastkajen {
+    auth: RwLock<Auth>,
+    credentials: Option<LoginRequest>,
+    /// Held while refreshing the bearer token, along with the error of the last refresh if it
+    /// failed, for the callers who waited on it.
+    refresh_lock: tokio::sync::Mutex<Option<Arc<LastkajenError>>>,
+    login_retries: u32,
+    client: http::HttpClient,
+    base_url: String,
+    retry: builder::RetryPolicy,
+    max_bytes_per_sec: Option<u64>,
+    max_json_bytes: Option<usize>,
+    max_download_bytes: Option<u64>,
+    decompress_json: bool,
+    staging_dir: Option<PathBuf>,
+    check_format: bool,
+    on_format_mismatch: Option<util::FormatMismatchHook>,
+    allowlist: builder::Allowlist,
+    package_files_cache: Mutex<HashMap<usize, (Instant, Vec<types::DataPackageFile>)>>,
+    /// Source folders of the published packages, for checking the allowlist's prefixes.
+    source_folders: Mutex<HashMap<usize, String>>,
+
     #[cfg(feature = "time")]
-    pub expiry_date_time: time::OffsetDateTime,
+    clock: Arc<dyn clock::Clock>,
 
-    pub token: types::Token,
-    client: reqwest::Client,
+    #[cfg(feature = "timing")]
+    on_timing: Option<timing::TimingHook>,
 }
 
 impl Lastkajen {
-    async fn check_status(response: reqwest::Response) -> Result<reqwest::Response> {
-        if response.status() != 200 {
+    /// Fail with [`LastkajenError::ApiError`] unless the response status is one of `allowed`.
+    async fn check_status_allowing(
+        response: reqwest::Response,
+        allowed: &[StatusCode],
+    ) -> Result<reqwest::Response> {
+        let status = response.status();
+        if !allowed.contains(&status) {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_retry_after);
+
             return Err(match response.text().await {
-                Ok(text) => LastkajenError::LastkajenError(text),
+                Ok(text) if is_quota_exceeded(status, &text) => {
+                    LastkajenError::QuotaExceeded { retry_after }
+                }
+                Ok(text) => LastkajenError::ApiError(status, text),
                 Err(err) => LastkajenError::ReqwestError(err),
             });
         }
@@ -89,22 +338,216 @@ impl Lastkajen {
         Ok(response)
     }
 
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    /// Authenticated GET against the API, retried according to the client's retry policy.
+    async fn get(&self, path: &str) -> Result<reqwest::Response> {
+        self.get_with_headers(path, HeaderMap::new()).await
+    }
+
+    async fn get_with_headers(&self, path: &str, headers: HeaderMap) -> Result<reqwest::Response> {
+        #[cfg(feature = "time")]
+        self.refresh_if_needed().await?;
+
+        let request = self
+            .auth()
+            .apply(self.client.get(self.url(path)))
+            .headers(headers)
+            .build()?;
+
+        self.send_with_retry(request).await
+    }
+
+    /// Authenticated GET, deserializing the JSON response body. An empty body (or a 204)
+    /// fails with [`LastkajenError::EmptyResponse`] rather than a parse error.
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        #[cfg(feature = "timing")]
+        let started = std::time::Instant::now();
+
+        let mut headers = HeaderMap::new();
+        if self.decompress_json {
+            headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        }
+        let res = self.get_with_headers(path, headers).await?;
+
+        #[cfg(feature = "timing")]
+        let headers = started.elapsed();
+
+        let body = self.read_json_body(res).await?;
+        let parsed = if body.iter().all(u8::is_ascii_whitespace) {
+            Err(LastkajenError::EmptyResponse)
+        } else {
+            parse_json(&body).map_err(|err| {
+                if is_maintenance_page(&body) {
+                    LastkajenError::Maintenance
+                } else {
+                    err.into()
+                }
+            })
+        };
+
+        #[cfg(feature = "timing")]
+        self.report_timing(path, headers, started.elapsed());
+
+        parsed
+    }
+
+    /// Read a JSON response body, up to the configured `max_json_bytes`, decompressing it if
+    /// it came gzip-encoded and that was asked for.
+    async fn read_json_body(&self, res: reqwest::Response) -> Result<Vec<u8>> {
+        let gzipped = self.decompress_json
+            && res
+                .headers()
+                .get(CONTENT_ENCODING)
+                .is_some_and(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"gzip"));
+
+        let body = self.read_limited_body(res).await?;
+        if !gzipped {
+            return Ok(body);
+        }
+
+        let mut decoder = flate2::read::GzDecoder::new(&body[..]);
+        let mut decoded = Vec::new();
+        match self.max_json_bytes {
+            Some(limit) => {
+                decoder.take(limit as u64 + 1).read_to_end(&mut decoded)?;
+                if decoded.len() > limit {
+                    return Err(LastkajenError::ResponseTooLarge(limit));
+                }
+            }
+            None => {
+                decoder.read_to_end(&mut decoded)?;
+            }
+        }
+
+        Ok(decoded)
+    }
+
+    async fn read_limited_body(&self, mut res: reqwest::Response) -> Result<Vec<u8>> {
+        let Some(limit) = self.max_json_bytes else {
+            return Ok(res.bytes().await?.to_vec());
+        };
+        if res.content_length().is_some_and(|len| len > limit as u64) {
+            return Err(LastkajenError::ResponseTooLarge(limit));
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = res.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(LastkajenError::ResponseTooLarge(limit));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(body)
+    }
+
+    /// Like [`Lastkajen::get_json`], treating an empty body as an empty list.
+    async fn get_json_list<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<Vec<T>> {
+        match self.get_json(path).await {
+            Err(LastkajenError::EmptyResponse) => Ok(Vec::new()),
+            result => result,
+        }
+    }
+
+    /// Authenticated GET with extra headers, leaving the response body unread.
+    async fn get_raw(&self, path: &str, headers: HeaderMap) -> Result<reqwest::Response> {
+        #[cfg(feature = "timing")]
+        let started = std::time::Instant::now();
+
+        let res = self.get_with_headers(path, headers).await?;
+
+        #[cfg(feature = "timing")]
+        {
+            let headers = started.elapsed();
+            self.report_timing(path, headers, headers);
+        }
+
+        Ok(res)
+    }
+
+    #[cfg(feature = "timing")]
+    fn report_timing(&self, path: &str, headers: std::time::Duration, total: std::time::Duration) {
+        if let Some(hook) = &self.on_timing {
+            hook.call(&timing::RequestTiming {
+                // Strip the query, which may carry file names or tokens.
+                path: path.split('?').next().unwrap_or(path).to_string(),
+                headers,
+                total,
+            });
+        }
+    }
+
+    async fn send_with_retry(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            // GET requests carry no body, so cloning never fails.
+            let current = request
+                .try_clone()
+                .ok_or_else(|| LastkajenError::LastkajenError("Request can't be retried".into()))?;
+
+            let mut retry_after = None;
+            let err = match self.client.execute(current).await {
+                Ok(res) => {
+                    retry_after = res
+                        .headers()
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(parse_retry_after);
+                    match Lastkajen::check_status_allowing(res, API_STATUSES).await {
+                        Ok(res) => return Ok(res),
+                        Err(err) => err,
+                    }
+                }
+                Err(err) => err,
+            };
+
+            if !self.retry.should_retry(attempt, &err) {
+                return Err(err);
+            }
+
+            // A rate limited request waits as long as the server asked, if longer.
+            let delay = self.retry.delay(attempt);
+            tokio::time::sleep(retry_after.map_or(delay, |wait| wait.max(delay))).await;
+            attempt += 1;
+        }
+    }
+
     /// Create new Lastkajen instance, fetching a bearer token.
+    ///
+    /// Use [`Lastkajen::builder`] to configure retries or the base url.
     pub async fn new(user_name: String, password: String) -> Result<Self> {
-        let token = Lastkajen::retrieve_token(user_name, password).await?;
+        Lastkajen::builder().build(user_name, password).await
+    }
 
-        Ok(Self {
-            #[cfg(feature = "time")]
-            expiry_date_time: time::OffsetDateTime::now_utc()
-                .saturating_add(time::Duration::seconds(token.expires_in as i64)),
+    /// Like [`Lastkajen::new`], also fetching the published packages right away over the
+    /// login's connection.
+    ///
+    /// Use [`LastkajenBuilder::build_with_catalog`] to configure retries or the base url.
+    pub async fn new_with_catalog(
+        user_name: String,
+        password: String,
+    ) -> Result<(Self, Vec<types::DataPackageFolder>)> {
+        Lastkajen::builder()
+            .build_with_catalog(user_name, password)
+            .await
+    }
 
-            token,
-            client: reqwest::Client::new(),
-        })
+    /// Create new Lastkajen instance authenticating with an API key header rather than a
+    /// bearer token, should the API offer it.
+    pub fn with_api_key(key: String) -> Result<Self> {
+        Lastkajen::builder().build_with_api_key(key)
+    }
+
+    /// Configure a new Lastkajen instance.
+    pub fn builder() -> LastkajenBuilder {
+        LastkajenBuilder::new()
     }
 
     /// Manually retrieve a new bearer token.
-    /// ```rust
+    /// ```rust,no_run
     /// # use tokio_test;
     /// # use std::env;
     /// # use dotenv::dotenv;
@@ -113,35 +556,210 @@ impl Lastkajen {
     /// #   dotenv().ok();
     /// #   let username = env::var("USERNAME").unwrap();
     /// #   let password = env::var("PASSWORD").unwrap();
-    ///     let token: Result::<types::Token, _> = Lastkajen::retrieve_token(username, password).await;
+    ///     let token: Result::<types::Token> = Lastkajen::retrieve_token(username, password).await;
     ///     assert!(token.is_ok());
     /// # })
     ///
     /// ```
     pub async fn retrieve_token(user_name: String, password: String) -> Result<types::Token> {
-        let params = [("UserName", user_name), ("Password", password)];
-        let client = reqwest::Client::new();
+        Lastkajen::login_with(LoginRequest::new(user_name, password)).await
+    }
 
-        let res = client
-            .post("https://lastkajen.trafikverket.se/api/Identity/Login")
-            .form(&params)
-            .send()
-            .await?;
+    /// Manually retrieve a new bearer token, posting extra form parameters beyond the username
+    /// and password should the login flow need them.
+    pub async fn login_with(login: LoginRequest) -> Result<types::Token> {
+        let token = Lastkajen::login(
+            &http::HttpClient::new(reqwest::Client::new()),
+            DEFAULT_BASE_URL,
+            &login,
+        )
+        .await?
+        .token;
+
+        #[cfg(feature = "time")]
+        let token = types::Token {
+            obtained_at: Some(time::OffsetDateTime::now_utc()),
+            ..token
+        };
+
+        Ok(token)
+    }
 
-        // TODO: de-tangle this.
-        Ok(Lastkajen::check_status(res).await?.json().await?)
+    async fn login(
+        client: &http::HttpClient,
+        base_url: &str,
+        login: &session::LoginRequest,
+    ) -> Result<session::LoggedIn> {
+        let request = client
+            .post(format!("{}/api/Identity/Login", base_url))
+            .form(&login.form())
+            .build()?;
+        let res = client.execute(request).await?;
+        let res = Lastkajen::check_status_allowing(res, LOGIN_STATUSES).await?;
+
+        #[cfg(feature = "time")]
+        let server_date = res
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date);
+
+        Ok(session::LoggedIn {
+            token: res.json().await?,
+
+            #[cfg(feature = "time")]
+            server_date,
+        })
+    }
+
+    /// Check that the API is reachable and the session is valid, e.g. for readiness probes.
+    ///
+    /// The API has no dedicated health endpoint, so this requests the user's orders like
+    /// [`Lastkajen::get_user_files`]. The body is dropped unread, but the server still builds
+    /// the whole listing, which grows with the number of orders; probe sparingly.
+    pub async fn ping(&self) -> Result<()> {
+        self.get(USER_FILES_PATH).await?;
+        Ok(())
     }
 
     /// Get available public data packages.
+    ///
+    /// Packages are in the order the server lists them, which may vary between calls. See
+    /// [`Lastkajen::get_published_packages_sorted`] for a stable order.
     pub async fn get_published_packages(&self) -> Result<Vec<types::DataPackageFolder>> {
-        let res = self
-            .client
-            .get("https://lastkajen.trafikverket.se/api/DataPackage/GetPublishedDataPackages")
-            .bearer_auth(&self.token.access_token)
-            .send()
-            .await?;
+        self.get_json_list(PUBLISHED_PACKAGES_PATH).await
+    }
+
+    /// Get available public data packages, sorted by id.
+    pub async fn get_published_packages_sorted(&self) -> Result<Vec<types::DataPackageFolder>> {
+        let mut packages = self.get_published_packages().await?;
+        packages.sort_by_key(|package| package.id);
+
+        Ok(packages)
+    }
+
+    /// Get available public data packages, skipping elements that fail to deserialize rather
+    /// than failing the whole listing. Skipped elements are reported alongside.
+    pub async fn get_published_packages_lenient(
+        &self,
+    ) -> Result<(Vec<types::DataPackageFolder>, Vec<types::DeserializeIssue>)> {
+        let values: Vec<serde_json::Value> = self.get_json_list(PUBLISHED_PACKAGES_PATH).await?;
+
+        let mut packages = Vec::with_capacity(values.len());
+        let mut issues = Vec::new();
+        for (index, value) in values.into_iter().enumerate() {
+            match serde_json::from_value(value) {
+                Ok(package) => packages.push(package),
+                Err(err) => issues.push(types::DeserializeIssue {
+                    index,
+                    error: err.to_string(),
+                }),
+            }
+        }
+
+        Ok((packages, issues))
+    }
+
+    /// Get available public data packages, leaving out those not marked as published.
+    ///
+    /// [`Lastkajen::get_published_packages`] returns the listing unfiltered.
+    pub async fn get_published_packages_visible(&self) -> Result<Vec<types::DataPackageFolder>> {
+        let mut packages = self.get_published_packages().await?;
+        packages.retain(|package| package.published);
+
+        Ok(packages)
+    }
+
+    /// Get available public data packages as an unread response, for handling the body yourself.
+    ///
+    /// `headers` are added to the request, e.g. for headers the typed api doesn't model.
+    pub async fn get_published_packages_raw(
+        &self,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response> {
+        self.get_raw(PUBLISHED_PACKAGES_PATH, headers).await
+    }
+
+    /// Get available public data packages grouped by target folder, keyed by folder id.
+    pub async fn get_published_packages_grouped(
+        &self,
+    ) -> Result<BTreeMap<usize, (types::TargetFolder, Vec<types::DataPackageFolder>)>> {
+        let packages = self.get_published_packages().await?;
+
+        let mut grouped = BTreeMap::new();
+        for package in packages {
+            grouped
+                .entry(package.target_folder.id)
+                .or_insert_with(|| (package.target_folder.clone(), Vec::new()))
+                .1
+                .push(package);
+        }
+
+        Ok(grouped)
+    }
+
+    /// Check whether the data package with the given id is published, failing with
+    /// [`LastkajenError::NotFound`] if no such package exists.
+    pub async fn is_package_published(&self, id: &usize) -> Result<bool> {
+        let packages = self.get_published_packages().await?;
+
+        packages
+            .iter()
+            .find(|package| package.id == *id)
+            .map(|package| package.published)
+            .ok_or_else(|| LastkajenError::NotFound(format!("Data package {}", id)))
+    }
+
+    /// Get the distinct target folders of the published packages (by id), sorted by path.
+    pub async fn list_target_folders(&self) -> Result<Vec<types::TargetFolder>> {
+        let packages = self.get_published_packages().await?;
+
+        let folders: BTreeMap<usize, types::TargetFolder> = packages
+            .into_iter()
+            .map(|package| (package.target_folder.id, package.target_folder))
+            .collect();
+        let mut folders: Vec<types::TargetFolder> = folders.into_values().collect();
+        folders.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(folders)
+    }
+
+    /// Get the published packages whose target folder is `prefix` or lies under it, ignoring
+    /// case. `/` and `\\` are both taken as separators, and trailing ones are ignored.
+    pub async fn packages_under_path(&self, prefix: &str) -> Result<Vec<types::DataPackageFolder>> {
+        let normalize = |path: &str| {
+            path.replace('/', "\\")
+                .trim_end_matches('\\')
+                .to_lowercase()
+        };
+        let prefix = normalize(prefix);
+
+        let mut packages = self.get_published_packages().await?;
+        packages.retain(|package| {
+            let path = normalize(&package.target_folder.path);
+            prefix.is_empty()
+                || path == prefix
+                || path
+                    .strip_prefix(&prefix)
+                    .is_some_and(|rest| rest.starts_with('\\'))
+        });
 
-        Ok(Lastkajen::check_status(res).await?.json().await?)
+        Ok(packages)
+    }
+
+    /// Get the sorted, deduplicated names of all counties with published packages.
+    pub async fn list_counties(&self) -> Result<Vec<String>> {
+        let packages = self.get_published_packages().await?;
+
+        let mut counties: Vec<String> = packages
+            .iter()
+            .filter_map(|package| package.county_name())
+            .map(String::from)
+            .collect();
+        counties.sort();
+        counties.dedup();
+
+        Ok(counties)
     }
 
     /// Get information and download links for a data package.
@@ -157,94 +775,1468 @@ impl Lastkajen {
         &self,
         id: &usize,
     ) -> Result<Vec<types::DataPackageFile>> {
-        let res = self
-            .client
-            .get(format!(
-                "https://lastkajen.trafikverket.se/api/DataPackage/GetDataPackageFiles/{}",
-                id
-            ))
-            .bearer_auth(&self.token.access_token)
-            .send()
-            .await?;
+        self.get_json_list(&package_files_path(id)).await
+    }
 
-        Ok(Lastkajen::check_status(res).await?.json().await?)
+    /// Get a data package's files, reusing the listing from an earlier call within `ttl`.
+    ///
+    /// Listings are cached in the client, see [`Lastkajen::clear_cache`].
+    pub async fn get_package_files_cached(
+        &self,
+        id: &usize,
+        ttl: Duration,
+    ) -> Result<Vec<types::DataPackageFile>> {
+        if let Some((fetched_at, files)) = self.package_files_cache().get(id) {
+            if fetched_at.elapsed() <= ttl {
+                return Ok(files.clone());
+            }
+        }
+
+        let fetched_at = Instant::now();
+        let files = self.get_package_files_from_id(id).await?;
+        self.package_files_cache()
+            .insert(*id, (fetched_at, files.clone()));
+
+        Ok(files)
     }
 
-    /// Get information on user orders.
-    pub async fn get_user_files(&self) -> Result<Vec<types::UserFile>> {
-        let res = self
-            .client
-            .get("https://lastkajen.trafikverket.se/api/file/GetUserFiles")
-            .bearer_auth(&self.token.access_token)
-            .send()
-            .await?;
+    /// Forget the listings cached by [`Lastkajen::get_package_files_cached`].
+    pub fn clear_cache(&self) {
+        self.package_files_cache().clear();
+    }
 
-        Ok(Lastkajen::check_status(res).await?.json().await?)
+    fn package_files_cache(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<usize, (Instant, Vec<types::DataPackageFile>)>> {
+        self.package_files_cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
     }
 
-    /// Get download token for a file-which are single use and valid for 60 seconds
-    pub async fn get_download_token(
+    /// Get a data package's (non-folder) files whose names end with `extension`, ignoring case,
+    /// e.g. `"_GeoPackage.zip"`.
+    pub async fn get_package_files_by_extension(
         &self,
-        category: types::DownloadCategory<'_>,
-    ) -> Result<types::DownloadToken> {
-        let url: String = match category {
-            types::DownloadCategory::User { file } => format!("https://lastkajen.trafikverket.se/api/file/GetUserFileDownloadToken?fileName={}", file),
-            types::DownloadCategory::Published { id, file } => format!("https://lastkajen.trafikverket.se/api/file/GetDataPackageDownloadToken?id={}&fileName={}", id, file),
-        };
+        id: &usize,
+        extension: &str,
+    ) -> Result<Vec<types::DataPackageFile>> {
+        let extension = extension.to_lowercase();
+        let mut files = self.get_package_files_from_id(id).await?;
+        files.retain(|file| !file.is_folder && file.name.to_lowercase().ends_with(&extension));
 
-        let res = Lastkajen::check_status(
-            self.client
-                .get(url)
-                .bearer_auth(&self.token.access_token)
-                .send()
-                .await?,
-        )
-        .await?;
+        Ok(files)
+    }
 
-        match category {
-            types::DownloadCategory::User { .. } => {
-                Ok(types::DownloadToken::User(res.json().await?))
-            }
-            types::DownloadCategory::Published { .. } => {
-                Ok(types::DownloadToken::Published(res.json().await?))
-            }
-        }
+    /// Get the current listing of one of a data package's files, e.g. for fresh
+    /// [`links`](types::DataPackageFile::links) to an entry cached for a while.
+    ///
+    /// The API has no endpoint for a single file, so this fetches the package's files, bypassing
+    /// the cache, and fails with [`LastkajenError::NotFound`] if none is named `file_name`.
+    pub async fn refresh_file(
+        &self,
+        package_id: &usize,
+        file_name: &str,
+    ) -> Result<types::DataPackageFile> {
+        self.get_package_files_from_id(package_id)
+            .await?
+            .into_iter()
+            .find(|file| file.name == file_name)
+            .ok_or_else(|| {
+                LastkajenError::NotFound(format!(
+                    "File {} in data package {}",
+                    file_name, package_id
+                ))
+            })
     }
 
-    /// Download data, expending a download token.
-    pub async fn download_with_token(
+    /// Get the most recent (non-folder) file of a data package.
+    ///
+    /// Without the `time` feature, dates are compared as ISO 8601 strings.
+    pub async fn latest_package_file(&self, id: &usize) -> Result<Option<types::DataPackageFile>> {
+        let files = self.get_package_files_from_id(id).await?;
+
+        Ok(files
+            .into_iter()
+            .filter(|file| !file.is_folder)
+            .max_by(|a, b| a.date_time.cmp(&b.date_time)))
+    }
+
+    /// Get a data package's files as an unread response, for handling the body yourself.
+    ///
+    /// `headers` are added to the request, e.g. for headers the typed api doesn't model.
+    pub async fn get_package_files_raw(
         &self,
-        download_token: types::DownloadToken,
-        writable: &mut dyn Write,
-    ) -> Result<()> {
-        let url: String = match download_token {
-            // No, they aren't interchangable for some reason.
-            types::DownloadToken::User(dltoken) => format!(
-                "https://lastkajen.trafikverket.se/api/file/GetFileStream?token={}",
-                dltoken
-            ),
-            types::DownloadToken::Published(dltoken) => format!(
-                "https://lastkajen.trafikverket.se/api/file/GetDataPackageFile?token={}",
-                dltoken
-            ),
-        };
+        id: &usize,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response> {
+        self.get_raw(&package_files_path(id), headers).await
+    }
 
-        let mut res = Lastkajen::check_status(self.client.get(url).send().await?).await?;
+    /// Get the files of every given package, fetching a few listings concurrently.
+    async fn get_files_of_packages(
+        &self,
+        packages: Vec<types::DataPackageFolder>,
+    ) -> Result<Vec<(types::DataPackageFolder, Vec<types::DataPackageFile>)>> {
+        futures::stream::iter(packages)
+            .map(|package| async move {
+                let files = self.get_package_files(&package).await?;
+                Ok((package, files))
+            })
+            .buffer_unordered(PACKAGE_FILES_CONCURRENCY)
+            .try_collect()
+            .await
+    }
 
-        while let Some(chunk) = res.chunk().await? {
-            writable.write_all(&chunk)?;
-        }
+    /// Stream every downloadable (non-folder) file of every published package, along with the
+    /// package's id, in no particular order.
+    ///
+    /// Package file listings are fetched a few at a time as the stream is polled.
+    pub fn all_downloadable_files(
+        &self,
+    ) -> impl Stream<Item = Result<(types::PackageId, types::DataPackageFile)>> + '_ {
+        self.downloadable_files_by_package(|_| true)
+            .try_filter_map(|(id, file)| futures::future::ok(file.map(|file| (id, file))))
+    }
 
-        Ok(())
+    /// Like [`Lastkajen::all_downloadable_files`] for the packages `include` accepts, following
+    /// each package's files with `(id, None)`. Files of a package are never interleaved with
+    /// another's.
+    pub(crate) fn downloadable_files_by_package<P>(
+        &self,
+        include: P,
+    ) -> impl Stream<Item = Result<(types::PackageId, Option<types::DataPackageFile>)>> + '_
+    where
+        P: Fn(types::PackageId) -> bool + 'static,
+    {
+        futures::stream::once(self.get_published_packages())
+            .map_ok(move |mut packages| {
+                packages.retain(|package| include(package.id));
+                futures::stream::iter(packages)
+                    .map(move |package| async move {
+                        let files = self.get_package_files(&package).await?;
+                        Ok::<_, LastkajenError>(futures::stream::iter(
+                            files
+                                .into_iter()
+                                .filter(|file| !file.is_folder)
+                                .map(Some)
+                                .chain([None])
+                                .map(move |file| Ok::<_, LastkajenError>((package.id, file))),
+                        ))
+                    })
+                    .buffer_unordered(PACKAGE_FILES_CONCURRENCY)
+                    .try_flatten()
+            })
+            .try_flatten()
     }
 
-    /// Download data, creating _and_ expending a download token.
-    pub async fn download_file(
+    /// Search both published package files and user files for names containing `query`,
+    /// ignoring case. Folders are left out.
+    ///
+    /// Every published package's file listing is fetched, so this is a fairly heavy call.
+    pub async fn search_files(&self, query: &str) -> Result<Vec<types::FoundFile>> {
+        let query = query.to_lowercase();
+        let matches =
+            |is_folder: bool, name: &str| !is_folder && name.to_lowercase().contains(&query);
+
+        let published = async {
+            let packages = self.get_published_packages().await?;
+            self.get_files_of_packages(packages).await
+        };
+        let (published, user_files) = futures::try_join!(published, self.get_user_files())?;
+
+        let published = published.into_iter().flat_map(|(package, files)| {
+            files
+                .into_iter()
+                .filter(|file| matches(file.is_folder, &file.name))
+                .map(move |file| types::FoundFile::Published(package.clone(), file))
+                .collect::<Vec<_>>()
+        });
+        let user_files = user_files
+            .into_iter()
+            .filter(|file| matches(file.is_folder, &file.name))
+            .map(types::FoundFile::User);
+
+        Ok(published.chain(user_files).collect())
+    }
+
+    /// Get the published packages with any file dated after `since`, in no particular order.
+    ///
+    /// Like [`Lastkajen::search_files`], every published package's file listing is fetched.
+    #[cfg(feature = "time")]
+    pub async fn packages_modified_since(
         &self,
-        category: types::DownloadCategory<'_>,
-        writable: &mut dyn Write,
-    ) -> Result<()> {
-        let download_token = self.get_download_token(category).await?;
-        self.download_with_token(download_token, writable).await
+        since: time::OffsetDateTime,
+    ) -> Result<Vec<types::DataPackageFolder>> {
+        let packages = self.get_published_packages().await?;
+        let packages = self.get_files_of_packages(packages).await?;
+
+        Ok(packages
+            .into_iter()
+            .filter(|(_, files)| files.iter().any(|file| file.date_time > since))
+            .map(|(package, _)| package)
+            .collect())
+    }
+
+    /// Get information on user orders.
+    ///
+    /// Files are in the order the server lists them, which may vary between calls. See
+    /// [`Lastkajen::get_user_files_sorted`] for a stable order.
+    pub async fn get_user_files(&self) -> Result<Vec<types::UserFile>> {
+        self.get_json_list(USER_FILES_PATH).await
     }
+
+    /// Get information on user orders, sorted by name and then date.
+    pub async fn get_user_files_sorted(&self) -> Result<Vec<types::UserFile>> {
+        let mut files = self.get_user_files().await?;
+        files.sort_by(|a, b| {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| a.date_time.cmp(&b.date_time))
+        });
+
+        Ok(files)
+    }
+
+    /// Get the user orders whose names aren't in `previously_seen`, e.g. to notify of completed
+    /// orders by polling. Keeping the set of seen names between runs is up to the caller.
+    pub async fn new_user_files_since(
+        &self,
+        previously_seen: &HashSet<String>,
+    ) -> Result<Vec<types::UserFile>> {
+        let mut files = self.get_user_files().await?;
+        files.retain(|file| !previously_seen.contains(&file.name));
+
+        Ok(files)
+    }
+
+    /// Count the user's orders and sum up their sizes, along with when they were made.
+    pub async fn user_files_summary(&self) -> Result<types::UserFilesSummary> {
+        let files = self.get_user_files().await?;
+
+        let mut summary = types::UserFilesSummary::default();
+        let mut date_times = Vec::new();
+        for file in files.iter().filter(|file| !file.is_folder) {
+            summary.files += 1;
+            match file.size_bytes() {
+                Some(bytes) => summary.total_bytes += bytes,
+                None => summary.unknown_sizes += 1,
+            }
+
+            #[cfg(feature = "time")]
+            let Some(date_time) = types::parse_date_time(&file.date_time) else {
+                continue;
+            };
+            #[cfg(feature = "time")]
+            let date = date_time.date().to_string();
+
+            // ISO 8601 date times order the same as text, and start with the date.
+            #[cfg(not(feature = "time"))]
+            let date_time = file.date_time.clone();
+            #[cfg(not(feature = "time"))]
+            let Some(date) = date_time.get(..10).map(str::to_string) else {
+                continue;
+            };
+
+            *summary.files_by_date.entry(date).or_default() += 1;
+            date_times.push(date_time);
+        }
+        summary.oldest = date_times.iter().min().cloned();
+        summary.newest = date_times.into_iter().max();
+
+        Ok(summary)
+    }
+
+    /// Get both the published packages and the user orders, fetching them concurrently. Fails
+    /// if either request does.
+    pub async fn get_all_listings(
+        &self,
+    ) -> Result<(Vec<types::DataPackageFolder>, Vec<types::UserFile>)> {
+        futures::try_join!(self.get_published_packages(), self.get_user_files())
+    }
+
+    /// Create a custom extract (user order).
+    ///
+    /// The Lastkajen API doesn't currently expose order creation, orders have to be placed on the
+    /// website. This always fails with [`LastkajenError::LastkajenError`] until it does.
+    pub async fn create_user_order(&self, _spec: types::OrderSpec) -> Result<types::OrderHandle> {
+        Err(LastkajenError::LastkajenError(
+            "order creation not supported".to_string(),
+        ))
+    }
+
+    /// Get information on user orders as an unread response, for handling the body yourself.
+    ///
+    /// `headers` are added to the request, e.g. for headers the typed api doesn't model.
+    pub async fn get_user_files_raw(&self, headers: HeaderMap) -> Result<reqwest::Response> {
+        self.get_raw(USER_FILES_PATH, headers).await
+    }
+
+    /// Get download token for a file-which are single use and valid for 60 seconds
+    pub async fn get_download_token(
+        &self,
+        category: types::DownloadCategory<'_>,
+    ) -> Result<types::DownloadToken> {
+        self.check_allowed(category).await?;
+
+        let path: String = match category {
+            types::DownloadCategory::User { file } => {
+                format!("/api/file/GetUserFileDownloadToken?fileName={}", file)
+            }
+            types::DownloadCategory::Published { id, file } => format!(
+                "/api/file/GetDataPackageDownloadToken?id={}&fileName={}",
+                id, file
+            ),
+        };
+
+        match category {
+            types::DownloadCategory::User { .. } => {
+                Ok(types::DownloadToken::User(self.get_json(&path).await?))
+            }
+            types::DownloadCategory::Published { .. } => {
+                Ok(types::DownloadToken::Published(self.get_json(&path).await?))
+            }
+        }
+    }
+
+    /// Refuse categories outside the client's allowlist, if it has one.
+    async fn check_allowed(&self, category: types::DownloadCategory<'_>) -> Result<()> {
+        let allowlist = &self.allowlist;
+        if !allowlist.is_set() {
+            return Ok(());
+        }
+
+        let (id, file) = match category {
+            types::DownloadCategory::Published { id, file } => (id, file),
+            types::DownloadCategory::User { file } => {
+                return Err(LastkajenError::NotAllowed(format!("user file {:?}", file)));
+            }
+        };
+        let refused = || LastkajenError::NotAllowed(format!("{:?} of package {}", file, id));
+
+        if let Some(package_ids) = &allowlist.package_ids {
+            if !package_ids.contains(id) {
+                return Err(refused());
+            }
+        }
+        if let Some(prefixes) = &allowlist.source_folder_prefixes {
+            let source_folder = match self.source_folder(id) {
+                Some(source_folder) => Some(source_folder),
+                // Not seen before, the package may have been published since the last listing.
+                None => {
+                    let packages = self.get_published_packages().await?;
+                    *self
+                        .source_folders
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner) = packages
+                        .into_iter()
+                        .map(|package| (package.id, package.source_folder))
+                        .collect();
+                    self.source_folder(id)
+                }
+            };
+            let allowed = source_folder.is_some_and(|source_folder| {
+                prefixes
+                    .iter()
+                    .any(|prefix| is_in_folder(&source_folder, prefix))
+            });
+            if !allowed {
+                return Err(refused());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn source_folder(&self, id: &usize) -> Option<String> {
+        self.source_folders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(id)
+            .cloned()
+    }
+
+    /// Get a download token along with the category it was minted for, so the two can be kept
+    /// together.
+    pub async fn get_paired_download_token<'a>(
+        &self,
+        category: types::DownloadCategory<'a>,
+    ) -> Result<(types::DownloadCategory<'a>, types::DownloadToken)> {
+        let token = self.get_download_token(category).await?;
+        debug_assert_eq!(category.kind(), token.category_kind());
+
+        Ok((category, token))
+    }
+
+    /// Download data into `sink`, expending a download token.
+    ///
+    /// The sink is [finalized](sink::DownloadSink::finalize) once the download completes, which
+    /// for writers means flushing, so buffered writers don't need to be flushed separately.
+    /// Transfer speed is capped if the client was built with
+    /// [`LastkajenBuilder::max_bytes_per_sec`].
+    ///
+    /// Failures aren't retried, as the token is expended by the first attempt. Prefer
+    /// [`Lastkajen::download_file`], which mints a new token for every attempt.
+    ///
+    /// The future can be dropped at any point, e.g. by a `select!` timeout. The response is then
+    /// dropped along with it, closing its connection rather than returning a half-read one to
+    /// the pool, so later requests aren't affected. Whatever chunks were written stay written and
+    /// the sink isn't finalized, so a partial download is the sink's to discard; see
+    /// [`Lastkajen::download_to_path`] for files which clean up after themselves.
+    pub async fn download_with_token(
+        &self,
+        download_token: types::DownloadToken,
+        mut sink: impl sink::DownloadSink,
+    ) -> Result<()> {
+        let res = self.open_download(download_token).await?;
+
+        self.for_each_chunk(res, |chunk| sink.write_chunk(chunk))
+            .await?;
+        sink.finalize()
+    }
+
+    /// Download an arbitrary Lastkajen-hosted link, e.g. a [`types::FileLink`] href or one copied
+    /// from the website, authenticating as for API requests.
+    ///
+    /// `href` may be relative to the base url. Absolute urls must share its scheme, host and
+    /// port, so the token is never sent anywhere else. Refused with
+    /// [`LastkajenError::NotAllowed`] if the client has an allowlist.
+    pub async fn download_href(&self, href: &str, writable: &mut dyn Write) -> Result<()> {
+        if self.allowlist.is_set() {
+            return Err(LastkajenError::NotAllowed(format!("link {:?}", href)));
+        }
+
+        let base = reqwest::Url::parse(&self.base_url)
+            .map_err(|err| LastkajenError::LastkajenError(format!("invalid base url: {}", err)))?;
+        let url = base
+            .join(href)
+            .map_err(|err| LastkajenError::LastkajenError(format!("invalid href: {}", err)))?;
+        if url.origin() != base.origin() {
+            return Err(LastkajenError::LastkajenError(format!(
+                "refusing to download from {}, not hosted at {}",
+                url, self.base_url
+            )));
+        }
+
+        #[cfg(feature = "time")]
+        self.refresh_if_needed().await?;
+
+        let request = self
+            .auth()
+            .apply(self.client.get(url))
+            .header(ACCEPT_ENCODING, IDENTITY)
+            .build()?;
+        let res = self.client.execute(request).await?;
+        let res = Lastkajen::check_status_allowing(res, DOWNLOAD_STATUSES).await?;
+
+        self.for_each_chunk(res, |chunk| Ok(writable.write_all(chunk)?))
+            .await?;
+        writable.flush()?;
+
+        Ok(())
+    }
+
+    /// Download data as a stream of chunks, expending the download token.
+    ///
+    /// The response body is only read as the stream is polled, so a slow consumer holds back the
+    /// transfer (down to the TCP window) instead of it piling up in memory. At most a chunk is
+    /// held at a time. The stream doesn't borrow the client, so it can be handed on as e.g. a
+    /// streaming response body.
+    pub async fn download_stream(
+        &self,
+        download_token: types::DownloadToken,
+    ) -> Result<impl Stream<Item = Result<bytes::Bytes>> + Send + 'static> {
+        let res = self.open_download(download_token).await?;
+        let throttle = self.max_bytes_per_sec.map(throttle::Throttle::new);
+        let budget = DownloadBudget::new(self.max_download_bytes, &res)?;
+
+        Ok(futures::stream::try_unfold(
+            (res, throttle, budget),
+            |(mut res, mut throttle, mut budget)| async move {
+                let Some(chunk) = res.chunk().await? else {
+                    return Ok(None);
+                };
+                budget.spend(chunk.len())?;
+                if let Some(throttle) = throttle.as_mut() {
+                    throttle.consume(chunk.len()).await;
+                }
+
+                Ok(Some((chunk, (res, throttle, budget))))
+            },
+        ))
+    }
+
+    /// Like [`Lastkajen::download_stream`], with a bounded read-ahead buffer of up to `capacity`
+    /// chunks (at least one).
+    ///
+    /// A spawned task keeps reading the response while the consumer works through earlier
+    /// chunks, so a CPU-bound consumer doesn't leave the connection idle. This trades memory
+    /// for throughput: up to `capacity` chunks, each as large as the server and connection
+    /// deliver them (typically some kB), are held at once. The task stops at the first error,
+    /// which the stream ends with, or when the stream is dropped. Must be called within a
+    /// Tokio runtime.
+    pub async fn download_stream_buffered(
+        &self,
+        download_token: types::DownloadToken,
+        capacity: usize,
+    ) -> Result<impl Stream<Item = Result<bytes::Bytes>> + Send + 'static> {
+        let mut stream = Box::pin(self.download_stream(download_token).await?);
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity.max(1));
+
+        let producer = tokio::spawn(async move {
+            while let Some(chunk) = stream.next().await {
+                let failed = chunk.is_err();
+                if sender.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(futures::stream::unfold(
+            (receiver, AbortOnDrop(producer)),
+            |(mut receiver, producer)| async move {
+                let chunk = receiver.recv().await?;
+                Some((chunk, (receiver, producer)))
+            },
+        ))
+    }
+
+    /// Like [`Lastkajen::download_stream`], as an [`AsyncRead`](tokio::io::AsyncRead) for piping
+    /// into writers with [`tokio::io::copy`]. Errors are converted into [`std::io::Error`]s.
+    pub async fn download_reader(
+        &self,
+        download_token: types::DownloadToken,
+    ) -> Result<impl tokio::io::AsyncRead + Send + Unpin + 'static> {
+        let stream = self.download_stream(download_token).await?;
+
+        Ok(tokio_util::io::StreamReader::new(Box::pin(
+            stream.map_err(std::io::Error::from),
+        )))
+    }
+
+    /// Like [`Lastkajen::download_with_token`], reporting progress after every received chunk.
+    pub async fn download_with_progress<F>(
+        &self,
+        download_token: types::DownloadToken,
+        writable: &mut dyn Write,
+        mut on_progress: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&progress::DownloadProgress),
+    {
+        let res = self.open_download(download_token).await?;
+        let mut tracker = progress::ProgressTracker::new(res.content_length());
+
+        self.for_each_chunk(res, |chunk| {
+            writable.write_all(chunk)?;
+            on_progress(&tracker.advance(chunk.len()));
+            Ok(())
+        })
+        .await?;
+        writable.flush()?;
+
+        Ok(())
+    }
+
+    /// Like [`Lastkajen::download_with_token`], checkpointing at every `interval`: `writable` is
+    /// flushed, then `on_checkpoint` is given the number of bytes written so far, e.g. to persist
+    /// it for resuming. A final checkpoint is made once the download is complete.
+    ///
+    /// An error returned from `on_checkpoint` aborts the download.
+    pub async fn download_with_checkpoints<F>(
+        &self,
+        download_token: types::DownloadToken,
+        writable: &mut dyn Write,
+        interval: progress::CheckpointInterval,
+        mut on_checkpoint: F,
+    ) -> Result<()>
+    where
+        F: FnMut(u64) -> Result<()>,
+    {
+        let res = self.open_download(download_token).await?;
+        let mut checkpointer = progress::Checkpointer::new(interval);
+        let mut bytes_done = 0;
+        let mut checkpointed = None;
+
+        self.for_each_chunk(res, |chunk| {
+            writable.write_all(chunk)?;
+            bytes_done += chunk.len() as u64;
+
+            if checkpointer.is_due(bytes_done) {
+                writable.flush()?;
+                on_checkpoint(bytes_done)?;
+                checkpointed = Some(bytes_done);
+            }
+            Ok(())
+        })
+        .await?;
+        writable.flush()?;
+
+        if checkpointed != Some(bytes_done) {
+            on_checkpoint(bytes_done)?;
+        }
+        Ok(())
+    }
+
+    /// Start a download as an unread response, expending a download token.
+    ///
+    /// `headers` are added to the request, e.g. for headers the typed api doesn't model. A
+    /// `206 Partial Content` answer to a `Range` header is passed on like a `200`.
+    pub async fn download_raw(
+        &self,
+        download_token: types::DownloadToken,
+        headers: HeaderMap,
+    ) -> Result<reqwest::Response> {
+        self.open_download_with_headers(download_token, headers, RANGE_STATUSES)
+            .await
+    }
+
+    /// Download bytes `start` to `end`, inclusive as in HTTP, of a file into `writable`,
+    /// creating _and_ expending a download token. E.g. for a look at a GeoPackage's header
+    /// without downloading all of it.
+    ///
+    /// Fails with [`LastkajenError::RangeNotSupported`] if the server answers with the whole
+    /// file instead, before any of it is read. A range reaching past the end of the file is cut
+    /// short by the server.
+    pub async fn download_range(
+        &self,
+        category: types::DownloadCategory<'_>,
+        start: u64,
+        end: u64,
+        writable: &mut dyn Write,
+    ) -> Result<()> {
+        if start > end {
+            return Err(LastkajenError::LastkajenError(format!(
+                "invalid byte range {}-{}",
+                start, end
+            )));
+        }
+
+        let range = HeaderValue::from_str(&format!("bytes={}-{}", start, end))
+            .map_err(|err| LastkajenError::LastkajenError(err.to_string()))?;
+        let headers = HeaderMap::from_iter([(reqwest::header::RANGE, range)]);
+        let res = self
+            .open_fresh_download_with(category, headers, RANGE_STATUSES)
+            .await?;
+
+        // A server may also answer with a different range than asked for.
+        let content_start = res
+            .headers()
+            .get(reqwest::header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("bytes "))
+            .and_then(|value| value.split('-').next())
+            .and_then(|value| value.parse::<u64>().ok());
+        if res.status() != StatusCode::PARTIAL_CONTENT || content_start != Some(start) {
+            return Err(LastkajenError::RangeNotSupported);
+        }
+
+        self.for_each_chunk(res, |chunk| Ok(writable.write_all(chunk)?))
+            .await?;
+        writable.flush()?;
+
+        Ok(())
+    }
+
+    async fn open_download(
+        &self,
+        download_token: types::DownloadToken,
+    ) -> Result<reqwest::Response> {
+        self.open_download_with_headers(download_token, HeaderMap::new(), DOWNLOAD_STATUSES)
+            .await
+    }
+
+    /// Downloads carry no authentication, but go through the same client (and connection pool)
+    /// as the download token request, so one usually reuses the other's kept-alive connection
+    /// rather than spending the token's short lifetime on a new handshake.
+    async fn open_download_with_headers(
+        &self,
+        download_token: types::DownloadToken,
+        headers: HeaderMap,
+        allowed: &[StatusCode],
+    ) -> Result<reqwest::Response> {
+        let url: String = match download_token {
+            // No, they aren't interchangable for some reason.
+            types::DownloadToken::User(dltoken) => {
+                self.url(&format!("/api/file/GetFileStream?token={}", dltoken))
+            }
+            types::DownloadToken::Published(dltoken) => {
+                self.url(&format!("/api/file/GetDataPackageFile?token={}", dltoken))
+            }
+        };
+
+        let request = self
+            .client
+            .get(url)
+            .headers(headers)
+            .header(ACCEPT_ENCODING, IDENTITY)
+            .build()?;
+        let res = self.client.execute(request).await?;
+        Lastkajen::check_status_allowing(res, allowed).await
+    }
+
+    /// Feed every chunk of a download response to `on_chunk`, returning the number of bytes.
+    async fn for_each_chunk<F>(&self, mut res: reqwest::Response, mut on_chunk: F) -> Result<u64>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let mut throttle = self.max_bytes_per_sec.map(throttle::Throttle::new);
+        let mut budget = DownloadBudget::new(self.max_download_bytes, &res)?;
+        let mut bytes = 0;
+        while let Some(chunk) = res.chunk().await? {
+            budget.spend(chunk.len())?;
+            on_chunk(&chunk)?;
+            bytes += chunk.len() as u64;
+
+            if let Some(throttle) = throttle.as_mut() {
+                throttle.consume(chunk.len()).await;
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Mint a download token and open the download, minting a second token if the first one
+    /// is rejected as expired. Nothing has been read at that point, so retrying is safe.
+    async fn open_fresh_download(
+        &self,
+        category: types::DownloadCategory<'_>,
+    ) -> Result<reqwest::Response> {
+        self.open_fresh_download_with(category, HeaderMap::new(), DOWNLOAD_STATUSES)
+            .await
+    }
+
+    async fn open_fresh_download_with(
+        &self,
+        category: types::DownloadCategory<'_>,
+        headers: HeaderMap,
+        allowed: &[StatusCode],
+    ) -> Result<reqwest::Response> {
+        let download_token = self.get_download_token(category).await?;
+        match self
+            .open_download_with_headers(download_token, headers.clone(), allowed)
+            .await
+        {
+            Err(err) if is_rejected_download_token(&err) => {
+                let download_token = self.get_download_token(category).await?;
+                self.open_download_with_headers(download_token, headers, allowed)
+                    .await
+            }
+            result => result,
+        }
+    }
+
+    /// Download data, creating _and_ expending a download token.
+    ///
+    /// Should the token expire before the download starts, a new one is minted and the download
+    /// retried once. Other failures are retried according to the client's retry policy, each
+    /// attempt with a freshly minted token as the previous one was expended, but only as long as
+    /// nothing has been written to `writable` yet. Unlike this,
+    /// [`Lastkajen::download_with_token`] is never retried, having no way of minting a new token.
+    pub async fn download_file(
+        &self,
+        category: types::DownloadCategory<'_>,
+        writable: &mut dyn Write,
+    ) -> Result<()> {
+        let mut attempt = 0;
+        let mut reminted = false;
+        loop {
+            // Minting retries on its own, so only the download itself is retried here.
+            let download_token = self.get_download_token(category).await?;
+
+            let mut written = 0;
+            let result = match self.open_download(download_token).await {
+                Ok(res) => {
+                    self.for_each_chunk(res, |chunk| {
+                        writable.write_all(chunk)?;
+                        written += chunk.len();
+                        Ok(())
+                    })
+                    .await
+                }
+                Err(err) => Err(err),
+            };
+
+            let err = match result {
+                Ok(_) => break,
+                // Whatever was written can't be taken back.
+                Err(err) if written > 0 => return Err(err),
+                Err(err) => err,
+            };
+
+            if is_rejected_download_token(&err) && !reminted {
+                reminted = true;
+                continue;
+            }
+            if !self.retry.should_retry(attempt, &err) {
+                return Err(err);
+            }
+            tokio::time::sleep(self.retry.delay(attempt)).await;
+            attempt += 1;
+        }
+        writable.flush()?;
+
+        Ok(())
+    }
+
+    /// Download data to several sinks at once, e.g. a file and something gathering statistics,
+    /// creating _and_ expending a download token. Every chunk is written to each sink in turn.
+    ///
+    /// Stops at the first sink failing, with [`LastkajenError::SinkFailed`] telling which.
+    pub async fn download_tee(
+        &self,
+        category: types::DownloadCategory<'_>,
+        sinks: &mut [&mut dyn Write],
+    ) -> Result<()> {
+        let res = self.open_fresh_download(category).await?;
+
+        self.for_each_chunk(res, |chunk| {
+            for (index, sink) in sinks.iter_mut().enumerate() {
+                sink.write_all(chunk)
+                    .map_err(|err| LastkajenError::SinkFailed(index, err))?;
+            }
+            Ok(())
+        })
+        .await?;
+        for (index, sink) in sinks.iter_mut().enumerate() {
+            sink.flush()
+                .map_err(|err| LastkajenError::SinkFailed(index, err))?;
+        }
+
+        Ok(())
+    }
+
+    /// Download data, creating _and_ expending a download token, while computing the SHA-256
+    /// digest of the content. Returns the number of bytes written and the digest.
+    #[cfg(feature = "checksum")]
+    pub async fn download_hashing(
+        &self,
+        category: types::DownloadCategory<'_>,
+        writable: &mut dyn Write,
+    ) -> Result<(u64, [u8; 32])> {
+        use sha2::{Digest, Sha256};
+
+        let res = self.open_fresh_download(category).await?;
+
+        let mut hasher = Sha256::new();
+        let bytes = self
+            .for_each_chunk(res, |chunk| {
+                writable.write_all(chunk)?;
+                hasher.update(chunk);
+                Ok(())
+            })
+            .await?;
+        writable.flush()?;
+
+        Ok((bytes, hasher.finalize().into()))
+    }
+
+    /// Download several files into a single tar archive written to `writer`, each as an entry
+    /// named after the file, creating _and_ expending a download token per file.
+    ///
+    /// Downloads are streamed straight into the archive one after the other. Entries need their
+    /// size up front though, so a download without a `Content-Length` is buffered in memory
+    /// first.
+    ///
+    /// Should a download fail, the archive is left unfinished, without the end of archive
+    /// marker, so the partial output can't pass for a complete archive. See
+    /// [`Lastkajen::download_to_archive_path`] to not leave it behind at all.
+    #[cfg(feature = "archive")]
+    pub async fn download_to_archive(
+        &self,
+        categories: &[types::DownloadCategory<'_>],
+        writer: &mut dyn Write,
+    ) -> Result<()> {
+        let mut archive = tar::Builder::new(writer);
+        match self.append_to_archive(&mut archive, categories).await {
+            Ok(()) => {
+                archive.into_inner()?.flush()?;
+                Ok(())
+            }
+            Err(err) => {
+                // Dropping the builder would finish the archive. It only holds the writer, so
+                // forgetting it leaks nothing.
+                std::mem::forget(archive);
+                Err(err)
+            }
+        }
+    }
+
+    /// Like [`Lastkajen::download_to_archive`], writing the archive to a file at `path`.
+    ///
+    /// The archive is first written to `<path>.part` next to the target, which is renamed to
+    /// `path` once every download is in and removed if any fails.
+    #[cfg(feature = "archive")]
+    pub async fn download_to_archive_path(
+        &self,
+        categories: &[types::DownloadCategory<'_>],
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mut part = PartFile::new(part_path(path));
+
+        let mut writer = BufWriter::new(fs::File::create(&part.path)?);
+        self.download_to_archive(categories, &mut writer).await?;
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+
+        fs::rename(&part.path, path)?;
+        part.keep = true;
+        Ok(())
+    }
+
+    /// Append a download of each of `categories` to `archive`, see
+    /// [`Lastkajen::download_to_archive`].
+    #[cfg(feature = "archive")]
+    async fn append_to_archive(
+        &self,
+        archive: &mut tar::Builder<&mut dyn Write>,
+        categories: &[types::DownloadCategory<'_>],
+    ) -> Result<()> {
+        let modified = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        for category in categories {
+            let file = match category {
+                types::DownloadCategory::Published { file, .. }
+                | types::DownloadCategory::User { file } => file,
+            };
+            let name = Path::new(file.as_str()).file_name().ok_or_else(|| {
+                LastkajenError::LastkajenError(format!("invalid file name {:?}", file))
+            })?;
+
+            let res = self.open_fresh_download(*category).await?;
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_mtime(modified);
+
+            let Some(size) = res.content_length() else {
+                let mut body = Vec::new();
+                self.for_each_chunk(res, |chunk| {
+                    body.extend_from_slice(chunk);
+                    Ok(())
+                })
+                .await?;
+                header.set_size(body.len() as u64);
+                archive.append_data(&mut header, name, body.as_slice())?;
+                continue;
+            };
+
+            header.set_size(size);
+            // Appending empty data writes just the header (and any long name entries), leaving
+            // the body to be streamed in after it.
+            archive.append_data(&mut header, name, std::io::empty())?;
+
+            let out = archive.get_mut();
+            let written = self
+                .for_each_chunk(res, |chunk| Ok(out.write_all(chunk)?))
+                .await?;
+            if written != size {
+                return Err(LastkajenError::LastkajenError(format!(
+                    "download of {:?} was {} bytes, expected {}",
+                    file, written, size
+                )));
+            }
+
+            // Entries are padded to whole 512 byte blocks.
+            let padding = (512 - size % 512) % 512;
+            out.write_all(&[0; 512][..padding as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Download every user file whose name matches `pattern` into `out_dir`, at most
+    /// `max_concurrent` at a time, returning each matching file along with how its download went.
+    ///
+    /// `pattern` is a glob if it contains `*` or `?` (e.g. `"*_Gävle_*.zip"`), otherwise it
+    /// matches names containing it. Files are saved as with [`Lastkajen::download_to_path`].
+    pub async fn download_user_files_matching(
+        &self,
+        pattern: &str,
+        out_dir: impl AsRef<Path>,
+        max_concurrent: usize,
+    ) -> Result<Vec<(types::UserFile, Result<()>)>> {
+        let out_dir = out_dir.as_ref();
+        let files = self.get_user_files().await?;
+
+        Ok(futures::stream::iter(files)
+            .filter(|file| {
+                futures::future::ready(!file.is_folder && matches_pattern(pattern, &file.name))
+            })
+            .map(|file| async move {
+                // Never let a name with separators write outside of `out_dir`.
+                let result = match Path::new(&file.name).file_name() {
+                    Some(name) => {
+                        let category = types::DownloadCategory::User { file: &file.name };
+                        self.download_to_path(category, out_dir.join(name)).await
+                    }
+                    None => Err(LastkajenError::LastkajenError(format!(
+                        "invalid file name {:?}",
+                        file.name
+                    ))),
+                };
+                (file, result)
+            })
+            .buffer_unordered(max_concurrent.max(1))
+            .collect()
+            .await)
+    }
+
+    /// Download a published package's file into `dir`, named as on the server, returning the
+    /// path it was saved to. Saved as with [`Lastkajen::download_to_path`].
+    pub async fn download_package_file(
+        &self,
+        package: &types::DataPackageFolder,
+        file: &types::DataPackageFile,
+        dir: impl AsRef<Path>,
+    ) -> Result<PathBuf> {
+        let name = Path::new(&file.name).file_name().ok_or_else(|| {
+            LastkajenError::LastkajenError(format!("invalid file name {:?}", file.name))
+        })?;
+        let path = dir.as_ref().join(name);
+
+        let category = types::DownloadCategory::Published {
+            id: &package.id,
+            file: &file.name,
+        };
+        self.download_to_path(category, &path).await?;
+
+        Ok(path)
+    }
+
+    /// Like [`Lastkajen::download_package_file`], then checks the size of the saved file against
+    /// the listed size. With `set_modified` the file's modification time is also set to the
+    /// listed date, for tools that compare files by it. That needs the `time` feature; without
+    /// it, `set_modified` fails before anything is downloaded.
+    ///
+    /// A size mismatch isn't an error, check
+    /// [`DownloadVerification::is_ok`](types::DownloadVerification::is_ok). The file is checked
+    /// once saved, so even a mismatched one has already been moved to its final path, replacing
+    /// any file there. Remove it if it isn't to be kept.
+    pub async fn download_package_file_verified(
+        &self,
+        package: &types::DataPackageFolder,
+        file: &types::DataPackageFile,
+        dir: impl AsRef<Path>,
+        set_modified: bool,
+    ) -> Result<types::DownloadVerification> {
+        #[cfg(not(feature = "time"))]
+        if set_modified {
+            return Err(LastkajenError::LastkajenError(
+                "setting the modification time needs the time feature".into(),
+            ));
+        }
+
+        let path = self.download_package_file(package, file, dir).await?;
+
+        let saved = std::fs::File::options().write(true).open(&path)?;
+        #[cfg(feature = "time")]
+        if set_modified {
+            saved.set_modified(file.date_time.into())?;
+        }
+        let bytes = saved.metadata()?.len();
+
+        Ok(types::DownloadVerification {
+            path,
+            bytes,
+            size_matches: file.size.matches(bytes),
+        })
+    }
+
+    /// Download every format of a dataset in a package, i.e. files named `base_name` followed by
+    /// the format (e.g. `"Gävleborgs_län_"` for `Gävleborgs_län_GeoPackage.zip` and
+    /// `Gävleborgs_län_Shape.zip`), into `out_dir`.
+    ///
+    /// Returns where each format, the rest of the name without its extension, was saved.
+    pub async fn download_all_formats(
+        &self,
+        package_id: &usize,
+        base_name: &str,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<BTreeMap<String, PathBuf>> {
+        let out_dir = out_dir.as_ref();
+        let files = self.get_package_files_from_id(package_id).await?;
+
+        let mut paths = BTreeMap::new();
+        for file in files.iter().filter(|file| !file.is_folder) {
+            let Some(rest) = file.name.strip_prefix(base_name) else {
+                continue;
+            };
+            let format = Path::new(rest)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let Some(name) = Path::new(&file.name).file_name() else {
+                continue;
+            };
+
+            let path = out_dir.join(name);
+            let category = types::DownloadCategory::Published {
+                id: package_id,
+                file: &file.name,
+            };
+            self.download_to_path(category, &path).await?;
+            paths.insert(format, path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Download data to a file at `path`, creating _and_ expending a download token.
+    ///
+    /// Data is first written to `<path>.part` next to the target (or in the
+    /// [staging directory](LastkajenBuilder::staging_dir)), which is renamed to `path` once the
+    /// download is complete and removed if it fails. A file at `path` is therefore always a
+    /// complete download.
+    ///
+    /// Dropping the future, e.g. on a timeout, cancels the download and removes the `.part`
+    /// file as well, leaving any earlier file at `path` untouched.
+    pub async fn download_to_path(
+        &self,
+        category: types::DownloadCategory<'_>,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let res = self.open_fresh_download(category).await?;
+
+        self.save_download(res, path.as_ref()).await
+    }
+
+    /// Like [`Lastkajen::download_to_path`], but first checks that the filesystem of `path` has
+    /// room for the download, failing with [`LastkajenError::InsufficientSpace`] otherwise.
+    ///
+    /// The size is taken from the `Content-Length` of the download response, before any of the
+    /// body is read, as download tokens are expended on the first request and can't be spent on a
+    /// separate `HEAD`. Downloads without a `Content-Length` aren't checked.
+    #[cfg(feature = "disk-space")]
+    pub async fn download_to_path_checked(
+        &self,
+        category: types::DownloadCategory<'_>,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let res = self.open_fresh_download(category).await?;
+
+        if let Some(needed) = res.content_length() {
+            let dir = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            let available = fs4::available_space(dir)?;
+            if needed > available {
+                return Err(LastkajenError::InsufficientSpace(needed, available));
+            }
+        }
+
+        self.save_download(res, path).await
+    }
+
+    /// Write a download response to `<path>.part`, renaming it to `path` once complete.
+    async fn save_download(&self, res: reqwest::Response, path: &Path) -> Result<()> {
+        let mut part = PartFile::new(self.part_path(path));
+
+        self.download_to_part(res, &part.path).await?;
+        if self.check_format || self.on_format_mismatch.is_some() {
+            self.check_downloaded_format(&part.path, path)?;
+        }
+
+        match fs::rename(&part.path, path) {
+            Ok(()) => {
+                part.keep = true;
+                Ok(())
+            }
+            // Only a staging directory can be on another filesystem.
+            Err(err) if part.path.parent() == path.parent() => Err(err.into()),
+            // Copy it next to the target first, so the target still only appears once complete.
+            Err(_) => {
+                let mut copy = PartFile::new(part_path(path));
+                fs::copy(&part.path, &copy.path)?;
+                fs::rename(&copy.path, path)?;
+                copy.keep = true;
+                Ok(())
+            }
+        }
+    }
+
+    /// Where a download to `path` is staged, in the staging directory if there is one on the
+    /// same filesystem, otherwise next to it.
+    ///
+    /// Targets in different directories can share a file name, so in the staging directory the
+    /// name is suffixed with a hash of the full target path, as in `file.zip.<hash>.part`.
+    fn part_path(&self, path: &Path) -> PathBuf {
+        use std::hash::{Hash, Hasher};
+
+        let part_path = part_path(path);
+        let Some(staging_dir) = &self.staging_dir else {
+            return part_path;
+        };
+
+        let target_dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let (Some(name), true) = (path.file_name(), same_filesystem(staging_dir, target_dir))
+        else {
+            return part_path;
+        };
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        fs::canonicalize(target_dir)
+            .unwrap_or_else(|_| target_dir.to_path_buf())
+            .join(name)
+            .hash(&mut hasher);
+        let mut staged_name = name.to_os_string();
+        staged_name.push(format!(".{:016x}.part", hasher.finish()));
+        staging_dir.join(staged_name)
+    }
+
+    async fn download_to_part(&self, res: reqwest::Response, part_path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(fs::File::create(part_path)?);
+        self.for_each_chunk(res, |chunk| Ok(writer.write_all(chunk)?))
+            .await?;
+
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    /// Report a download staged at `part_path` not being in the format `path` implies to the
+    /// `on_format_mismatch` hook, failing it if `check_format` is set.
+    fn check_downloaded_format(&self, part_path: &Path, path: &Path) -> Result<()> {
+        let Some((expected, found)) = format_mismatch(part_path, path)? else {
+            return Ok(());
+        };
+        if let Some(hook) = &self.on_format_mismatch {
+            hook.call(path, expected, found);
+        }
+        if self.check_format {
+            return Err(LastkajenError::FormatMismatch(expected, found));
+        }
+        Ok(())
+    }
+}
+
+/// Whether a response body is one of the HTML pages shown during maintenance.
+fn is_maintenance_page(body: &[u8]) -> bool {
+    let body = String::from_utf8_lossy(body).to_lowercase();
+    body.contains("<html") && (body.contains("underhåll") || body.contains("maintenance"))
+}
+
+/// Whether an error response is the API refusing further downloads on the account's quota: a
+/// 429 or 403 mentioning it. A bare 429 is a short-term rate limit, and stays a retryable
+/// [`LastkajenError::ApiError`].
+fn is_quota_exceeded(status: StatusCode, body: &str) -> bool {
+    let body = body.to_lowercase();
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::FORBIDDEN
+    ) && (body.contains("quota") || body.contains("kvot"))
+}
+
+/// Running total of a download's bytes against the configured `max_download_bytes`.
+struct DownloadBudget {
+    limit: Option<u64>,
+    spent: u64,
+}
+
+impl DownloadBudget {
+    /// Fails right away if the response announces more than `limit` bytes.
+    fn new(limit: Option<u64>, res: &reqwest::Response) -> Result<Self> {
+        if let Some(limit) = limit {
+            if res.content_length().is_some_and(|len| len > limit) {
+                return Err(LastkajenError::DownloadTooLarge(limit));
+            }
+        }
+
+        Ok(Self { limit, spent: 0 })
+    }
+
+    /// Count a chunk, failing before it's used if it would go over the limit, whatever the
+    /// response announced.
+    fn spend(&mut self, len: usize) -> Result<()> {
+        self.spent += len as u64;
+        match self.limit {
+            Some(limit) if self.spent > limit => Err(LastkajenError::DownloadTooLarge(limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Aborts the task when dropped, so it doesn't outlive whatever consumes its results.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Parse a `Retry-After` header, either a number of seconds or (with the `time` feature) an
+/// HTTP date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    #[cfg(feature = "time")]
+    {
+        let wait = parse_http_date(value)? - time::OffsetDateTime::now_utc();
+        Some(wait.try_into().unwrap_or(Duration::ZERO))
+    }
+
+    #[cfg(not(feature = "time"))]
+    None
+}
+
+/// Parse an HTTP date, as in `Date` and `Retry-After` headers.
+#[cfg(feature = "time")]
+fn parse_http_date(value: &str) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::parse(value.trim(), &time::format_description::well_known::Rfc2822).ok()
+}
+
+/// Whether a download failed on the token being refused, which for the otherwise
+/// unauthenticated download endpoints means it expired or was already used.
+fn is_rejected_download_token(err: &LastkajenError) -> bool {
+    matches!(
+        err,
+        LastkajenError::ApiError(status, _)
+            if matches!(
+                *status,
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::GONE
+            )
+    )
+}
+
+/// Parse a JSON response body, with simd-json if the `simd` feature is enabled.
+///
+/// simd-json parses in place, so it's handed a copy. Should it fail, serde_json parses the body
+/// again for the error, keeping errors the same whichever parser is used.
+fn parse_json<T: serde::de::DeserializeOwned>(body: &[u8]) -> serde_json::Result<T> {
+    #[cfg(feature = "simd")]
+    if let Ok(value) = simd_json::serde::from_slice(&mut body.to_vec()) {
+        return Ok(value);
+    }
+
+    serde_json::from_slice(body)
+}
+
+/// A download's staging file, removed when dropped unless kept. Dropping covers every way out of
+/// a download to a path, including the future being dropped mid-transfer.
+struct PartFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl PartFile {
+    fn new(path: PathBuf) -> Self {
+        Self { path, keep: false }
+    }
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Sibling `<path>.part` file, on the same filesystem as `path` so renaming it is atomic.
+fn part_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    path.with_file_name(file_name)
+}
+
+/// The expected and found formats if the content at `part_path` isn't in the format the
+/// extension of `path` implies, if it implies one.
+fn format_mismatch(
+    part_path: &Path,
+    path: &Path,
+) -> Result<Option<(util::DetectedFormat, util::DetectedFormat)>> {
+    use std::io::Read;
+
+    let Some(expected) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(util::DetectedFormat::from_extension)
+    else {
+        return Ok(None);
+    };
+
+    let mut start = Vec::with_capacity(512);
+    fs::File::open(part_path)?
+        .take(512)
+        .read_to_end(&mut start)?;
+
+    let found = util::detect_format(&start);
+    Ok((found != expected).then_some((expected, found)))
+}
+
+/// Whether two existing directories are on the same filesystem. Outside of Unix this can't be
+/// told, so only checks that `a` exists.
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev(),
+            _ => false,
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = b;
+        a.is_dir()
+    }
+}
+
+/// Whether `path` is the folder `prefix` or lies under it, matching whole path components only.
+fn is_in_folder(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches(['\\', '/']);
+    prefix.is_empty()
+        || path
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['\\', '/']))
+}
+
+/// Match `name` against `pattern`: a glob if it contains `*` (any run of characters) or `?` (any
+/// single character), otherwise a substring.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return name.contains(pattern);
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Greedy matching, going back only to the latest `*` and letting it swallow one more
+    // character. Earlier stars never need revisiting, so this is O(n·m) rather than exponential.
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    backtrack = Some((star, swallowed + 1));
+                    p = star + 1;
+                    n = swallowed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
 }
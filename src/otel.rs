@@ -0,0 +1,82 @@
+use std::future::Future;
+
+use opentelemetry::propagation::Injector;
+use opentelemetry::trace::{SpanKind, Status, TraceContextExt, Tracer};
+use opentelemetry::{global, Context, KeyValue};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::Result;
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// The url without its query (where download tokens go) or credentials.
+fn redacted_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+    url.to_string()
+}
+
+/// Send `request` within a client span, propagating the current trace context in its headers.
+///
+/// Only the method, redacted url, status and content length are recorded; never headers or
+/// error messages, so the bearer and download tokens stay out of traces.
+pub(crate) async fn traced<F, Fut>(
+    mut request: reqwest::Request,
+    send: F,
+) -> Result<reqwest::Response>
+where
+    F: FnOnce(reqwest::Request) -> Fut,
+    Fut: Future<Output = Result<reqwest::Response>>,
+{
+    let tracer = global::tracer("lastkajen");
+    let span = tracer
+        .span_builder(request.method().to_string())
+        .with_kind(SpanKind::Client)
+        .with_attributes([
+            KeyValue::new("http.method", request.method().to_string()),
+            KeyValue::new("http.url", redacted_url(request.url())),
+        ])
+        .start_with_context(&tracer, &Context::current());
+    let cx = Context::current_with_span(span);
+
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut HeaderInjector(request.headers_mut()))
+    });
+
+    let result = send(request).await;
+
+    let span = cx.span();
+    match &result {
+        Ok(res) => {
+            span.set_attribute(KeyValue::new(
+                "http.status_code",
+                i64::from(res.status().as_u16()),
+            ));
+            if let Some(length) = res.content_length() {
+                span.set_attribute(KeyValue::new("http.response_content_length", length as i64));
+            }
+            if res.status().is_client_error() || res.status().is_server_error() {
+                span.set_status(Status::error(""));
+            }
+        }
+        // reqwest and middleware errors alike may mention the url, token and all.
+        Err(_) => span.set_status(Status::error("request failed")),
+    }
+    span.end();
+
+    result
+}
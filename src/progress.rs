@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Window over which the transfer rate is averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+// -----------------------------------------------------
+
+/// Progress of an ongoing download, see
+/// [`Lastkajen::download_with_progress`](crate::Lastkajen::download_with_progress).
+#[derive(Debug, Clone)]
+pub struct DownloadProgress {
+    /// Bytes downloaded so far.
+    pub bytes_done: u64,
+    /// Total size of the download, if the server reported it.
+    pub total: Option<u64>,
+    /// Time since the download started.
+    pub elapsed: Duration,
+    /// Transfer rate, averaged over the last few seconds to smooth out jitter.
+    pub bytes_per_sec: f64,
+    /// Estimated time remaining, if the total size and a transfer rate are known.
+    pub eta: Option<Duration>,
+}
+
+impl DownloadProgress {
+    /// Fraction of the download done, between 0 and 1, if the total size is known.
+    pub fn fraction(&self) -> Option<f64> {
+        self.total
+            .filter(|total| *total > 0)
+            .map(|total| self.bytes_done as f64 / total as f64)
+    }
+}
+
+/// How often [`Lastkajen::download_with_checkpoints`](crate::Lastkajen::download_with_checkpoints)
+/// checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointInterval {
+    /// After at least this many bytes since the last checkpoint.
+    Bytes(u64),
+    /// After at least this long since the last checkpoint.
+    Every(Duration),
+}
+
+/// Decides when a checkpoint is due.
+pub(crate) struct Checkpointer {
+    interval: CheckpointInterval,
+    last_bytes: u64,
+    last_at: Instant,
+}
+
+impl Checkpointer {
+    pub(crate) fn new(interval: CheckpointInterval) -> Self {
+        Self {
+            interval,
+            last_bytes: 0,
+            last_at: Instant::now(),
+        }
+    }
+
+    /// Whether a checkpoint is due at `bytes_done`, in which case it counts as taken.
+    pub(crate) fn is_due(&mut self, bytes_done: u64) -> bool {
+        let due = match self.interval {
+            CheckpointInterval::Bytes(bytes) => bytes_done - self.last_bytes >= bytes,
+            CheckpointInterval::Every(duration) => self.last_at.elapsed() >= duration,
+        };
+        if due {
+            self.last_bytes = bytes_done;
+            self.last_at = Instant::now();
+        }
+
+        due
+    }
+}
+
+// -----------------------------------------------------
+
+pub(crate) struct ProgressTracker {
+    total: Option<u64>,
+    started: Instant,
+    bytes_done: u64,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(total: Option<u64>) -> Self {
+        let started = Instant::now();
+        Self {
+            total,
+            started,
+            bytes_done: 0,
+            samples: VecDeque::from([(started, 0)]),
+        }
+    }
+
+    /// Account for `bytes` more having been downloaded.
+    pub(crate) fn advance(&mut self, bytes: usize) -> DownloadProgress {
+        let now = Instant::now();
+        self.bytes_done += bytes as u64;
+        self.samples.push_back((now, self.bytes_done));
+
+        // Keep one sample older than the window, so the window is always fully covered.
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) > RATE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        let (oldest_time, oldest_bytes) = self.samples[0];
+        let window = now.duration_since(oldest_time).as_secs_f64();
+        let bytes_per_sec = if window > 0.0 {
+            (self.bytes_done - oldest_bytes) as f64 / window
+        } else {
+            0.0
+        };
+
+        let eta = self
+            .total
+            .filter(|_| bytes_per_sec > 0.0)
+            .map(|total| total.saturating_sub(self.bytes_done) as f64 / bytes_per_sec)
+            // A huge total at a crawl doesn't fit a Duration, report no estimate rather than panic.
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok());
+
+        DownloadProgress {
+            bytes_done: self.bytes_done,
+            total: self.total,
+            elapsed: now.duration_since(self.started),
+            bytes_per_sec,
+            eta,
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::fmt;
+use std::ops::Deref;
+#[cfg(feature = "time")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLockReadGuard};
+
+use zeroize::Zeroize;
+
+use crate::{types, Lastkajen, LastkajenError, Result, API_KEY_HEADER};
+
+/// Bearer tokens are refreshed this long, or half their lifetime if shorter, before they expire.
+#[cfg(feature = "time")]
+const REFRESH_MARGIN: time::Duration = time::Duration::seconds(60);
+
+/// How often a paused refresh task checks whether the client is gone.
+#[cfg(feature = "time")]
+const PAUSED_POLL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// -----------------------------------------------------
+
+/// How requests are authenticated.
+#[derive(Debug)]
+pub(crate) enum Auth {
+    /// Bearer token from a username/password login.
+    Bearer {
+        token: types::Token,
+
+        #[cfg(feature = "time")]
+        expiry_date_time: time::OffsetDateTime,
+        /// How far the server's clock was ahead of ours at login, if it said.
+        #[cfg(feature = "time")]
+        clock_skew: Option<time::Duration>,
+    },
+    ApiKey(String),
+}
+
+impl Auth {
+    pub(crate) fn bearer(token: types::Token) -> Self {
+        Self::Bearer {
+            #[cfg(feature = "time")]
+            expiry_date_time: token.expiry_date_time().unwrap_or_else(|| {
+                time::OffsetDateTime::now_utc()
+                    .saturating_add(time::Duration::seconds(token.expires_in as i64))
+            }),
+            #[cfg(feature = "time")]
+            clock_skew: None,
+
+            token,
+        }
+    }
+
+    pub(crate) fn apply(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self {
+            Self::Bearer { token, .. } => request.bearer_auth(&token.access_token),
+            Self::ApiKey(key) => request.header(API_KEY_HEADER, key),
+        }
+    }
+
+    /// Overwrite the bearer token in memory, marking it expired. API keys are kept.
+    fn clear(&mut self) {
+        if let Self::Bearer {
+            token,
+            #[cfg(feature = "time")]
+            expiry_date_time,
+            ..
+        } = self
+        {
+            token.access_token.zeroize();
+
+            #[cfg(feature = "time")]
+            {
+                *expiry_date_time = time::OffsetDateTime::UNIX_EPOCH;
+            }
+        }
+    }
+}
+
+/// A login response's bearer token, along with the `Date` the server answered with.
+pub(crate) struct LoggedIn {
+    pub(crate) token: types::Token,
+
+    #[cfg(feature = "time")]
+    pub(crate) server_date: Option<time::OffsetDateTime>,
+}
+
+#[cfg(feature = "time")]
+impl LoggedIn {
+    /// Authenticate with the token, received at `now` by the local clock.
+    pub(crate) fn into_auth(self, now: time::OffsetDateTime) -> Auth {
+        let token = types::Token {
+            obtained_at: Some(now),
+            ..self.token
+        };
+        let mut auth = Auth::bearer(token);
+        if let Auth::Bearer { clock_skew, .. } = &mut auth {
+            *clock_skew = self.server_date.map(|date| date - now);
+        }
+
+        auth
+    }
+}
+
+/// Form posted to log in for a bearer token, see [`Lastkajen::login_with`].
+///
+/// A client built with one keeps it for refreshing the bearer token, so `extra` parameters are
+/// sent again with every refresh.
+#[derive(Clone)]
+pub struct LoginRequest {
+    pub user_name: String,
+    pub password: String,
+    /// Additional form parameters, sent after the username and password.
+    pub extra: Vec<(String, String)>,
+}
+
+impl LoginRequest {
+    pub fn new(user_name: String, password: String) -> Self {
+        Self {
+            user_name,
+            password,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Add a form parameter beyond the username and password.
+    pub fn param(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra.push((name.into(), value.into()));
+        self
+    }
+
+    pub(crate) fn form(&self) -> Vec<(&str, &str)> {
+        [
+            ("UserName", self.user_name.as_str()),
+            ("Password", self.password.as_str()),
+        ]
+        .into_iter()
+        .chain(
+            self.extra
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .collect()
+    }
+}
+
+/// Values of the extra parameters are redacted as well, being as likely to be secrets.
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let extra: Vec<_> = self
+            .extra
+            .iter()
+            .map(|(name, _)| (name, "<redacted>"))
+            .collect();
+        f.debug_struct("LoginRequest")
+            .field("user_name", &self.user_name)
+            .field("password", &"<redacted>")
+            .field("extra", &extra)
+            .finish()
+    }
+}
+
+// -----------------------------------------------------
+
+/// Scope guard for a client's bearer token, see [`Lastkajen::session`].
+///
+/// Dereferences to the client. When the guard is dropped (or [closed](Session::close)) the token
+/// is zeroized in memory, so it doesn't linger once the work is done.
+#[derive(Debug)]
+pub struct Session<'a> {
+    client: &'a Lastkajen,
+}
+
+impl Session<'_> {
+    /// End the session, clearing the token.
+    ///
+    /// The Lastkajen API has no way of revoking a token, so this currently does the same as
+    /// dropping the guard. Prefer it anyway, should revocation become possible.
+    pub async fn close(self) {
+        drop(self);
+    }
+}
+
+impl Deref for Session<'_> {
+    type Target = Lastkajen;
+
+    fn deref(&self) -> &Lastkajen {
+        self.client
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.client
+            .auth
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+// -----------------------------------------------------
+
+/// Handle to the background refresh task, see [`Lastkajen::spawn_refresh_task`].
+///
+/// Pausing only affects the background task. Requests still refresh an expiring token on
+/// demand through [`Lastkajen::refresh_if_needed`], so an idle app that makes no requests makes
+/// no logins either.
+#[cfg(feature = "time")]
+#[derive(Debug)]
+pub struct RefreshHandle {
+    task: tokio::task::JoinHandle<()>,
+    paused: Arc<AtomicBool>,
+    resumed: Arc<tokio::sync::Notify>,
+}
+
+#[cfg(feature = "time")]
+impl RefreshHandle {
+    /// Stop refreshing until [resumed](Self::resume). A refresh already underway completes.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    /// Resume refreshing, within a second if the token is already due.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.resumed.notify_one();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// End the task for good.
+    pub fn abort(&self) {
+        self.task.abort();
+    }
+
+    /// Whether the task has ended, either aborted or after the client was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+// -----------------------------------------------------
+
+impl Lastkajen {
+    /// Start a [`Session`], clearing the bearer token once it ends.
+    ///
+    /// ```rust,no_run
+    /// # use tokio_test;
+    /// # use std::env;
+    /// # use lastkajen::*;
+    /// # tokio_test::block_on(async {
+    /// #   let username = env::var("USERNAME").unwrap();
+    /// #   let password = env::var("PASSWORD").unwrap();
+    ///     let lastkajen = Lastkajen::new(username, password).await.unwrap();
+    ///
+    ///     let session = lastkajen.session();
+    ///     let files = session.get_user_files().await;
+    ///     session.close().await;
+    /// # })
+    /// ```
+    ///
+    /// Clients logged in with a username and password log in again on their next request after
+    /// the session, with the `time` feature. API keys are unaffected.
+    pub fn session(&self) -> Session<'_> {
+        Session { client: self }
+    }
+
+    pub(crate) fn auth(&self) -> RwLockReadGuard<'_, Auth> {
+        self.auth.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set_auth(&self, auth: Auth) {
+        *self.auth.write().unwrap_or_else(PoisonError::into_inner) = auth;
+    }
+
+    /// The current bearer token, unless authenticating with an API key.
+    pub fn token(&self) -> Option<types::Token> {
+        match &*self.auth() {
+            Auth::Bearer { token, .. } => Some(token.clone()),
+            Auth::ApiKey(_) => None,
+        }
+    }
+
+    /// Whether the client is logged in with an external (non-Trafikverket) account, see
+    /// [`Token::is_external`](types::Token::is_external). Always `false` for clients using an API
+    /// key.
+    pub fn is_external_account(&self) -> bool {
+        match &*self.auth() {
+            Auth::Bearer { token, .. } => token.is_external,
+            Auth::ApiKey(_) => false,
+        }
+    }
+
+    /// When the current bearer token expires, unless authenticating with an API key.
+    #[cfg(feature = "time")]
+    pub fn expiry_date_time(&self) -> Option<time::OffsetDateTime> {
+        match &*self.auth() {
+            Auth::Bearer {
+                expiry_date_time, ..
+            } => Some(*expiry_date_time),
+            Auth::ApiKey(_) => None,
+        }
+    }
+
+    /// How far the server's clock is ahead of the local one (negative if behind), going by the
+    /// `Date` header of the last login response. Unknown if the server sent none, and for
+    /// clients using an API key.
+    ///
+    /// Only accurate to a second or so, the header having whole seconds. Token expiry doesn't
+    /// depend on it: tokens last a number of seconds from when they're received, so it's
+    /// measured by the local clock alone, and a skewed clock refreshes neither early nor late.
+    #[cfg(feature = "time")]
+    pub fn clock_skew(&self) -> Option<time::Duration> {
+        match &*self.auth() {
+            Auth::Bearer { clock_skew, .. } => *clock_skew,
+            Auth::ApiKey(_) => None,
+        }
+    }
+
+    /// When the current bearer token should be refreshed.
+    #[cfg(feature = "time")]
+    fn refresh_date_time(&self) -> Option<time::OffsetDateTime> {
+        match &*self.auth() {
+            Auth::Bearer {
+                token,
+                expiry_date_time,
+                ..
+            } if self.credentials.is_some() => {
+                let half_life = time::Duration::seconds(token.expires_in as i64 / 2);
+                Some(*expiry_date_time - REFRESH_MARGIN.min(half_life))
+            }
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "time")]
+    fn needs_refresh(&self) -> bool {
+        self.refresh_date_time()
+            .is_some_and(|refresh| refresh <= self.clock.now_utc())
+    }
+
+    /// Log in again for a new bearer token, regardless of whether the current one has expired.
+    ///
+    /// Transient failures are retried, see
+    /// [`LastkajenBuilder::login_retries`](crate::LastkajenBuilder::login_retries), before
+    /// failing with [`LastkajenError::RefreshFailed`]. Does nothing for clients authenticating
+    /// with an API key.
+    pub async fn refresh(&self) -> Result<()> {
+        let mut last_failure = self.refresh_lock.lock().await;
+        self.refresh_locked(&mut last_failure).await
+    }
+
+    /// Refresh the bearer token if it expires within a minute (or half its lifetime), returning
+    /// whether it was refreshed.
+    ///
+    /// This is also done automatically before every API request. Concurrent callers share a
+    /// single refresh: only one logs in, the others wait for it and return `false`, or the same
+    /// [`LastkajenError::RefreshFailed`] if it failed.
+    #[cfg(feature = "time")]
+    pub async fn refresh_if_needed(&self) -> Result<bool> {
+        if !self.needs_refresh() {
+            return Ok(false);
+        }
+
+        let mut last_failure = match self.refresh_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                // Someone else is refreshing, share their outcome rather than retrying too.
+                return match &*self.refresh_lock.lock().await {
+                    Some(err) => Err(LastkajenError::RefreshFailed(err.clone())),
+                    None => Ok(false),
+                };
+            }
+        };
+        // Someone else may have refreshed between the check and taking the lock.
+        if !self.needs_refresh() {
+            return Ok(false);
+        }
+
+        self.refresh_locked(&mut last_failure).await?;
+        Ok(true)
+    }
+
+    /// Log in again with the refresh lock held, recording the outcome for waiting callers.
+    async fn refresh_locked(&self, last_failure: &mut Option<Arc<LastkajenError>>) -> Result<()> {
+        let result = self.login_again().await.map_err(Arc::new);
+        *last_failure = result.as_ref().err().cloned();
+
+        result.map_err(LastkajenError::RefreshFailed)
+    }
+
+    async fn login_again(&self) -> Result<()> {
+        let Some(credentials) = &self.credentials else {
+            return Ok(());
+        };
+
+        let mut attempt = 0;
+        let logged_in = loop {
+            let result = Lastkajen::login(&self.client, &self.base_url, credentials).await;
+
+            match result {
+                Err(err) if attempt < self.login_retries && err.is_retryable() => {
+                    tokio::time::sleep(self.retry.delay(attempt)).await;
+                    attempt += 1;
+                }
+                result => break result?,
+            }
+        };
+
+        #[cfg(feature = "time")]
+        self.set_auth(logged_in.into_auth(self.clock.now_utc()));
+        #[cfg(not(feature = "time"))]
+        self.set_auth(Auth::bearer(logged_in.token));
+
+        Ok(())
+    }
+
+    /// Keep the bearer token fresh in the background, refreshing it shortly before it expires.
+    ///
+    /// The task ends once every other `Arc` of the client has been dropped, or when aborted
+    /// through the returned handle. Dropping the handle leaves it running. Failed refreshes are
+    /// retried after a short delay. Must be called from within a tokio runtime.
+    #[cfg(feature = "time")]
+    pub fn spawn_refresh_task(self: &Arc<Self>) -> RefreshHandle {
+        let client = Arc::downgrade(self);
+        let paused = Arc::new(AtomicBool::new(false));
+        let resumed = Arc::new(tokio::sync::Notify::new());
+
+        let task = tokio::spawn({
+            let paused = paused.clone();
+            let resumed = resumed.clone();
+
+            async move {
+                loop {
+                    if paused.load(Ordering::SeqCst) {
+                        // Wake up now and then to notice the client being dropped.
+                        let _ = tokio::time::timeout(PAUSED_POLL, resumed.notified()).await;
+                        if client.strong_count() == 0 {
+                            return;
+                        }
+                        continue;
+                    }
+
+                    let Some(delay) = client.upgrade().map(|client| client.refresh_delay()) else {
+                        return;
+                    };
+                    tokio::time::sleep(delay).await;
+                    if paused.load(Ordering::SeqCst) {
+                        continue;
+                    }
+
+                    let Some(client) = client.upgrade() else {
+                        return;
+                    };
+                    if client.refresh_if_needed().await.is_err() {
+                        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                    }
+                }
+            }
+        });
+
+        RefreshHandle {
+            task,
+            paused,
+            resumed,
+        }
+    }
+
+    /// Time until the bearer token should be refreshed.
+    #[cfg(feature = "time")]
+    fn refresh_delay(&self) -> std::time::Duration {
+        self.refresh_date_time()
+            .map(|refresh| refresh - self.clock.now_utc())
+            .and_then(|delay| std::time::Duration::try_from(delay).ok())
+            .unwrap_or_default()
+            .max(std::time::Duration::from_secs(1))
+    }
+}
@@ -0,0 +1,29 @@
+use std::io::Write;
+
+use crate::Result;
+
+/// Destination for a download's data, e.g. an object store's multipart upload, see
+/// [`Lastkajen::download_with_token`](crate::Lastkajen::download_with_token).
+///
+/// Implemented for every [`Write`]r, which covers files, buffers and `&mut` references to them.
+pub trait DownloadSink {
+    /// Take the next chunk of the download, in order.
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Complete the destination once every chunk has been written, e.g. by committing an
+    /// upload. Not called if the download fails.
+    fn finalize(self) -> Result<()>
+    where
+        Self: Sized;
+}
+
+impl<W: Write> DownloadSink for W {
+    fn write_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        Ok(self.write_all(chunk)?)
+    }
+
+    /// Flushes the writer.
+    fn finalize(mut self) -> Result<()> {
+        Ok(self.flush()?)
+    }
+}
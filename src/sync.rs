@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{DataPackageFile, PackageId};
+use crate::{Lastkajen, Result};
+
+// -----------------------------------------------------
+
+/// How a local directory compares to a package's files, see [`Lastkajen::plan_sync`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub package_id: PackageId,
+    /// Files missing locally, or whose local copy differs.
+    pub to_download: Vec<DataPackageFile>,
+    pub up_to_date: Vec<DataPackageFile>,
+    /// Names of local files which aren't part of the package.
+    pub extra: Vec<String>,
+}
+
+impl SyncPlan {
+    /// Whether the local directory already matches the package, extra files aside.
+    pub fn is_up_to_date(&self) -> bool {
+        self.to_download.is_empty()
+    }
+}
+
+impl Lastkajen {
+    /// Compare the files of a package with those in `local_dir`, without downloading anything.
+    ///
+    /// A local file is up to date if its size agrees with the listed one, when that can be parsed
+    /// (see [`FileSize::matches`](crate::types::FileSize::matches)), and with the `time` feature
+    /// if it wasn't last modified before the listed date. A missing `local_dir` counts as empty.
+    pub async fn plan_sync(
+        &self,
+        package_id: PackageId,
+        local_dir: impl AsRef<Path>,
+    ) -> Result<SyncPlan> {
+        let local_dir = local_dir.as_ref();
+        let files = self.get_package_files_from_id(&package_id).await?;
+
+        let mut plan = SyncPlan {
+            package_id,
+            ..SyncPlan::default()
+        };
+        let mut names = HashSet::new();
+        for file in files.into_iter().filter(|file| !file.is_folder) {
+            let Some(name) = Path::new(&file.name).file_name() else {
+                continue;
+            };
+            names.insert(name.to_os_string());
+
+            match fs::metadata(local_dir.join(name)) {
+                Ok(local) if is_up_to_date(&file, &local) => plan.up_to_date.push(file),
+                _ => plan.to_download.push(file),
+            }
+        }
+
+        let entries = match fs::read_dir(local_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(plan),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() || names.contains(&entry.file_name()) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Leftovers of interrupted downloads aren't worth reporting.
+            if !name.ends_with(".part") {
+                plan.extra.push(name);
+            }
+        }
+        plan.extra.sort();
+
+        Ok(plan)
+    }
+}
+
+fn is_up_to_date(file: &DataPackageFile, local: &fs::Metadata) -> bool {
+    if !local.is_file() || file.size.matches(local.len()) == Some(false) {
+        return false;
+    }
+
+    #[cfg(feature = "time")]
+    if let Ok(modified) = local.modified() {
+        return time::OffsetDateTime::from(modified) >= file.date_time;
+    }
+
+    true
+}
@@ -0,0 +1,30 @@
+use std::time::{Duration, Instant};
+
+/// Paces a transfer to an average rate by sleeping whenever it gets ahead of schedule.
+pub(crate) struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    /// Account for `bytes` more having been transferred, waiting until the rate is respected.
+    pub(crate) async fn consume(&mut self, bytes: usize) {
+        self.transferred += bytes as u64;
+
+        let scheduled =
+            Duration::from_secs_f64(self.transferred as f64 / self.bytes_per_sec as f64);
+        let elapsed = self.started.elapsed();
+        if scheduled > elapsed {
+            tokio::time::sleep(scheduled - elapsed).await;
+        }
+    }
+}
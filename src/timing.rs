@@ -0,0 +1,40 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+// -----------------------------------------------------
+
+/// Coarse timing of a single API request, reported through
+/// [`LastkajenBuilder::on_timing`](crate::LastkajenBuilder::on_timing).
+///
+/// reqwest doesn't expose DNS or connect timings, so only time to the response headers and in
+/// total are measured. Both include any retries. Requests failing before their body could be
+/// read aren't reported.
+///
+/// JSON API requests (listings, download tokens) are timed until their body has been read and
+/// deserialized. The `*_raw` listing methods leave the body to the caller, so for them `total`
+/// is the same as `headers`. Downloads aren't timed.
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+    /// API path of the request, without query parameters.
+    pub path: String,
+    /// Time until the response headers were received.
+    pub headers: Duration,
+    /// Time until the request was done with, see above.
+    pub total: Duration,
+}
+
+#[derive(Clone)]
+pub(crate) struct TimingHook(pub(crate) Arc<dyn Fn(&RequestTiming) + Send + Sync>);
+
+impl TimingHook {
+    pub(crate) fn call(&self, timing: &RequestTiming) {
+        (self.0)(timing)
+    }
+}
+
+impl fmt::Debug for TimingHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TimingHook")
+    }
+}
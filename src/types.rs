@@ -1,98 +1,880 @@
-use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 // -----------------------------------------------------
 // See https://lastkajen2-p.ea.trafikverket.se/assets/Lastkajen2_API_Information.pdf for more
 // information.
 
 // Bearer token for the Swedish Transport Administration's Lastkajen API.
+//
+// Deliberately not `Serialize`, so the access token can't end up in plain text by accident.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Token {
     pub access_token: String,
     pub expires_in: usize,
+    /// Whether the account is an external one (a registered user outside of Trafikverket)
+    /// rather than an internal Trafikverket account. No endpoints are documented as being
+    /// restricted by it.
     pub is_external: bool,
+
+    /// When the token was retrieved, set by [`Lastkajen::retrieve_token`](crate::Lastkajen::retrieve_token).
+    #[cfg(feature = "time")]
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub obtained_at: Option<time::OffsetDateTime>,
+}
+
+#[cfg(feature = "time")]
+impl Token {
+    /// When the token expires, if it's known when it was obtained.
+    pub fn expiry_date_time(&self) -> Option<time::OffsetDateTime> {
+        self.obtained_at
+            .map(|at| at.saturating_add(time::Duration::seconds(self.expires_in as i64)))
+    }
 }
 
 // -----------------------------------------------------
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PackageType {
     Published,
     User,
 }
 
+/// Id of a published data package.
+pub type PackageId = usize;
+
 // -----------------------------------------------------
+// Response fields are camelCase, but accept PascalCase as well since some environments have been
+// seen returning it.
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct TargetFolder {
+    #[serde(alias = "Id")]
     pub id: usize,
+    #[serde(alias = "Name")]
     pub name: String,
+    #[serde(alias = "Path")]
     pub path: String,
 }
 
 /// Published Data Package
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DataPackageFolder {
+    #[serde(alias = "Id")]
     pub id: usize,
+    #[serde(alias = "TargetFolder")]
     pub target_folder: TargetFolder,
+    #[serde(alias = "SourceFolder")]
     pub source_folder: String,
+    #[serde(alias = "Name")]
     pub name: String,
+    #[serde(alias = "Description")]
     pub description: String,
+    #[serde(alias = "Published")]
     pub published: bool,
 }
 
+impl DataPackageFolder {
+    /// County segment of the source folder, e.g. `Gävleborgs län` for
+    /// `Datapaket\Länsfiler NVDB-data\Gävleborgs län`. `None` for packages that aren't
+    /// county files.
+    pub fn county_name(&self) -> Option<&str> {
+        let (_, county) = self.source_folder.split_once("Länsfiler NVDB-data\\")?;
+        let county = county.split('\\').next()?;
+
+        county.ends_with(" län").then_some(county)
+    }
+
+    /// County of the package, see [`DataPackageFolder::county_name`].
+    pub fn county(&self) -> Option<County> {
+        self.county_name()?.parse().ok()
+    }
+}
+
 // -----------------------------------------------------
 
-#[derive(Debug, Clone, Deserialize)]
+/// Swedish county (län).
+///
+/// Parses full names as well as short, underscored and ASCII-only variants, falling back to
+/// [`County::Other`]. Displays as the full name.
+///
+/// ```rust
+/// # use lastkajen::types::County;
+/// assert_eq!("Gävleborgs län".parse(), Ok(County::Gavleborg));
+/// assert_eq!("gavleborg".parse(), Ok(County::Gavleborg));
+/// assert_eq!("Västra_Götalands_län".parse(), Ok(County::VastraGotaland));
+/// assert_eq!(County::Dalarna.to_string(), "Dalarnas län");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum County {
+    Stockholm,
+    Uppsala,
+    Sodermanland,
+    Ostergotland,
+    Jonkoping,
+    Kronoberg,
+    Kalmar,
+    Gotland,
+    Blekinge,
+    Skane,
+    Halland,
+    VastraGotaland,
+    Varmland,
+    Orebro,
+    Vastmanland,
+    Dalarna,
+    Gavleborg,
+    Vasternorrland,
+    Jamtland,
+    Vasterbotten,
+    Norrbotten,
+    /// Anything not recognized, as given.
+    Other(String),
+}
+
+impl County {
+    /// Every county, in order of their county codes.
+    pub const ALL: [County; 21] = [
+        Self::Stockholm,
+        Self::Uppsala,
+        Self::Sodermanland,
+        Self::Ostergotland,
+        Self::Jonkoping,
+        Self::Kronoberg,
+        Self::Kalmar,
+        Self::Gotland,
+        Self::Blekinge,
+        Self::Skane,
+        Self::Halland,
+        Self::VastraGotaland,
+        Self::Varmland,
+        Self::Orebro,
+        Self::Vastmanland,
+        Self::Dalarna,
+        Self::Gavleborg,
+        Self::Vasternorrland,
+        Self::Jamtland,
+        Self::Vasterbotten,
+        Self::Norrbotten,
+    ];
+
+    /// Name without `län`, e.g. `Gävleborg`.
+    pub fn short_name(&self) -> &str {
+        match self {
+            Self::Stockholm => "Stockholm",
+            Self::Uppsala => "Uppsala",
+            Self::Sodermanland => "Södermanland",
+            Self::Ostergotland => "Östergötland",
+            Self::Jonkoping => "Jönköping",
+            Self::Kronoberg => "Kronoberg",
+            Self::Kalmar => "Kalmar",
+            Self::Gotland => "Gotland",
+            Self::Blekinge => "Blekinge",
+            Self::Skane => "Skåne",
+            Self::Halland => "Halland",
+            Self::VastraGotaland => "Västra Götaland",
+            Self::Varmland => "Värmland",
+            Self::Orebro => "Örebro",
+            Self::Vastmanland => "Västmanland",
+            Self::Dalarna => "Dalarna",
+            Self::Gavleborg => "Gävleborg",
+            Self::Vasternorrland => "Västernorrland",
+            Self::Jamtland => "Jämtland",
+            Self::Vasterbotten => "Västerbotten",
+            Self::Norrbotten => "Norrbotten",
+            Self::Other(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for County {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Other(name) => f.write_str(name),
+            // The few names written without a genitive s.
+            Self::Uppsala | Self::Kalmar | Self::Blekinge | Self::Skane | Self::Orebro => {
+                write!(f, "{} län", self.short_name())
+            }
+            _ => write!(f, "{}s län", self.short_name()),
+        }
+    }
+}
+
+impl std::str::FromStr for County {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        // Lowercase ASCII with single spaces, e.g. "gavleborgs lan".
+        let fold = |name: &str| -> String {
+            name.to_lowercase()
+                .replace(['å', 'ä'], "a")
+                .replace('ö', "o")
+                .replace('_', " ")
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+
+        let folded = fold(name);
+        let folded = folded.strip_suffix(" lan").unwrap_or(&folded);
+
+        Ok(Self::ALL
+            .into_iter()
+            .find(|county| {
+                let short = fold(county.short_name());
+                folded == short || folded.strip_suffix('s') == Some(short.as_str())
+            })
+            .unwrap_or_else(|| Self::Other(name.to_string())))
+    }
+}
+
+// -----------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FileLink {
+    #[serde(alias = "Href")]
     pub href: String,
+    #[serde(alias = "Rel")]
     pub rel: String,
+    #[serde(alias = "Method")]
     pub method: String,
+    #[serde(alias = "IsTemplated")]
     pub is_templated: bool,
 }
 
+impl FileLink {
+    /// Expand a templated href (RFC 6570) with `params`, or return it unchanged if it isn't
+    /// templated.
+    ///
+    /// Supports simple (`{id}`), reserved (`{+path}`), fragment (`{#frag}`), label (`{.ext}`),
+    /// path segment (`{/seg}`), path parameter (`{;p}`) and query (`{?a,b}`, `{&c}`)
+    /// expressions. Missing parameters are left out. The operators RFC 6570 reserves for future
+    /// use (`=`, `,`, `!`, `@` and `|`) fail.
+    ///
+    /// ```rust
+    /// # use std::collections::HashMap;
+    /// # use lastkajen::types::FileLink;
+    /// let link = FileLink {
+    ///     href: "/api/file/{id}{?fileName}".into(),
+    ///     rel: "download".into(),
+    ///     method: "GET".into(),
+    ///     is_templated: true,
+    /// };
+    /// let params = HashMap::from([("id", "12".to_string()), ("fileName", "Gävle län.zip".to_string())]);
+    /// assert_eq!(link.resolve(&params).unwrap(), "/api/file/12?fileName=G%C3%A4vle%20l%C3%A4n.zip");
+    /// ```
+    pub fn resolve(&self, params: &HashMap<&str, String>) -> crate::Result<String> {
+        if !self.is_templated {
+            return Ok(self.href.clone());
+        }
+
+        let mut resolved = String::with_capacity(self.href.len());
+        let mut rest = self.href.as_str();
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            resolved.push_str(&rest[..start]);
+            resolved.push_str(&expand_expression(&rest[start + 1..start + end], params)?);
+            rest = &rest[start + end + 1..];
+        }
+        resolved.push_str(rest);
+
+        Ok(resolved)
+    }
+
+    /// The href joined against `base`, the API host (`https://lastkajen.trafikverket.se` unless
+    /// configured otherwise), as a browser would resolve it. Absolute hrefs are returned
+    /// unchanged, as is the href if `base` isn't a valid url. Templated hrefs should be
+    /// [resolved](Self::resolve) first.
+    pub fn absolute_url(&self, base: &str) -> String {
+        if reqwest::Url::parse(&self.href).is_ok() {
+            return self.href.clone();
+        }
+
+        reqwest::Url::parse(base)
+            .and_then(|base| base.join(&self.href))
+            .map_or_else(|_| self.href.clone(), String::from)
+    }
+
+    /// The link's HTTP method, failing on anything but the standard verbs.
+    ///
+    /// ```rust
+    /// # use lastkajen::types::FileLink;
+    /// let mut link = FileLink {
+    ///     href: "/api/file".into(),
+    ///     rel: "download".into(),
+    ///     method: "get".into(),
+    ///     is_templated: false,
+    /// };
+    /// assert_eq!(link.http_method().unwrap(), reqwest::Method::GET);
+    ///
+    /// link.method = "FETCH".into();
+    /// assert!(link.http_method().is_err());
+    /// ```
+    pub fn http_method(&self) -> crate::Result<reqwest::Method> {
+        match self.method.to_uppercase().as_str() {
+            "GET" => Ok(reqwest::Method::GET),
+            "POST" => Ok(reqwest::Method::POST),
+            "PUT" => Ok(reqwest::Method::PUT),
+            "DELETE" => Ok(reqwest::Method::DELETE),
+            "HEAD" => Ok(reqwest::Method::HEAD),
+            "OPTIONS" => Ok(reqwest::Method::OPTIONS),
+            "CONNECT" => Ok(reqwest::Method::CONNECT),
+            "PATCH" => Ok(reqwest::Method::PATCH),
+            "TRACE" => Ok(reqwest::Method::TRACE),
+            _ => Err(crate::LastkajenError::LastkajenError(format!(
+                "Unknown HTTP method {:?}",
+                self.method
+            ))),
+        }
+    }
+}
+
+/// Expand a single URI Template expression, the part between the braces.
+fn expand_expression(expression: &str, params: &HashMap<&str, String>) -> crate::Result<String> {
+    let (operator, names) = match expression.chars().next() {
+        Some(c @ ('+' | '#' | '.' | '/' | ';' | '?' | '&')) => (Some(c), &expression[1..]),
+        Some(c @ ('=' | ',' | '!' | '@' | '|')) => {
+            return Err(crate::LastkajenError::LastkajenError(format!(
+                "Unsupported URI template operator {:?} in {{{}}}",
+                c, expression
+            )))
+        }
+        _ => (None, expression),
+    };
+    // Named expressions write empty values as `name=`, except for path parameters, `name`.
+    let (prefix, separator, named, if_empty, reserved) = match operator {
+        Some('+') => ("", ",", false, "", true),
+        Some('#') => ("#", ",", false, "", true),
+        Some('.') => (".", ".", false, "", false),
+        Some('/') => ("/", "/", false, "", false),
+        Some(';') => (";", ";", true, "", false),
+        Some('?') => ("?", "&", true, "=", false),
+        Some('&') => ("&", "&", true, "=", false),
+        _ => ("", ",", false, "", false),
+    };
+
+    let values: Vec<String> = names
+        .split(',')
+        .filter_map(|name| {
+            let value = params.get(name.trim())?;
+            let value = percent_encode(value, reserved);
+            Some(if !named {
+                value
+            } else if value.is_empty() {
+                format!("{}{}", name.trim(), if_empty)
+            } else {
+                format!("{}={}", name.trim(), value)
+            })
+        })
+        .collect();
+
+    if values.is_empty() {
+        return Ok(String::new());
+    }
+
+    Ok(format!("{}{}", prefix, values.join(separator)))
+}
+
+/// Percent-encode everything but unreserved characters, and reserved ones if `reserved`.
+fn percent_encode(value: &str, reserved: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        let keep = byte.is_ascii_alphanumeric()
+            || b"-._~".contains(&byte)
+            || (reserved && b":/?#[]@!$&'()*+,;=".contains(&byte));
+        if keep {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+
+    encoded
+}
+
+/// File size as reported by the API, e.g. `"1,5 GB"`, along with its parsed byte count.
+///
+/// Displays as the original string. Sizes compare by byte count; unparseable sizes only compare
+/// equal to identical strings.
+///
+/// ```rust
+/// # use lastkajen::types::FileSize;
+/// let size = FileSize::parse("1,5 kB");
+/// assert_eq!(size.bytes(), Some(1536));
+/// assert_eq!(size.to_string(), "1,5 kB");
+/// assert!(size > FileSize::parse("1 kB"));
+/// assert_eq!(FileSize::parse("n/a").bytes(), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileSize {
+    text: String,
+    bytes: Option<u64>,
+}
+
+impl FileSize {
+    pub fn parse(text: &str) -> Self {
+        Self {
+            text: text.to_string(),
+            bytes: parse_size(text),
+        }
+    }
+
+    /// The size as reported by the API.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The size in bytes, if it could be parsed.
+    pub fn bytes(&self) -> Option<u64> {
+        self.bytes
+    }
+
+    /// The size in bytes, reading `decimal_separator` as the decimal separator rather than
+    /// guessing it. Any other `,` or `.` is taken to group thousands.
+    pub fn bytes_with_locale(&self, decimal_separator: char) -> Option<u64> {
+        parse_size_with_separator(&self.text, decimal_separator)
+    }
+
+    /// Whether `bytes` agrees with the size to the precision it's reported with, e.g. anything
+    /// from 1.45 to 1.55 GB for `"1,5 GB"`. `None` if the size couldn't be parsed.
+    ///
+    /// ```rust
+    /// # use lastkajen::types::FileSize;
+    /// let size = FileSize::parse("1,5 kB");
+    /// assert_eq!(size.matches(1500), Some(true));
+    /// assert_eq!(size.matches(1700), Some(false));
+    /// ```
+    pub fn matches(&self, bytes: u64) -> Option<bool> {
+        let expected = self.bytes?;
+        let (number, multiplier) = split_size(&self.text, guess_decimal_separator(&self.text)?)?;
+        let decimals = number
+            .split_once('.')
+            .map_or(0, |(_, decimals)| decimals.len());
+
+        let tolerance = multiplier as f64 / 10f64.powi(decimals as i32) / 2.0;
+        Some(bytes.abs_diff(expected) as f64 <= tolerance.max(0.5))
+    }
+}
+
+impl fmt::Display for FileSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Serialize for FileSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl PartialEq for FileSize {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.bytes, other.bytes) {
+            (Some(bytes), Some(other_bytes)) => bytes == other_bytes,
+            _ => self.text == other.text,
+        }
+    }
+}
+
+impl PartialOrd for FileSize {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.bytes, other.bytes) {
+            (Some(bytes), Some(other_bytes)) => Some(bytes.cmp(&other_bytes)),
+            _ => (self.text == other.text).then_some(Ordering::Equal),
+        }
+    }
+}
+
+fn deserialize_file_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FileSize, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    Ok(FileSize::parse(&text))
+}
+
+/// Parse sizes like `"512 B"`, `"1,5 GB"` or `"2048"` (bytes), using binary multiples.
+///
+/// The decimal separator is guessed: a lone `,` or `.` is decimal (so `"1,234 MB"` is 1.234 MB,
+/// as the API formats sizes the Swedish way), as is the last of the two when both appear
+/// (`"1.234,5 MB"`). A separator appearing more than once groups thousands.
+pub(crate) fn parse_size(text: &str) -> Option<u64> {
+    parse_size_with_separator(text, guess_decimal_separator(text)?)
+}
+
+fn guess_decimal_separator(text: &str) -> Option<char> {
+    let commas = text.matches(',').count();
+    let periods = text.matches('.').count();
+
+    Some(match (commas, periods) {
+        (1, 0) => ',',
+        (0, 1) => '.',
+        (0, _) => ',',
+        (_, 0) => '.',
+        _ => text
+            .rfind([',', '.'])
+            .and_then(|i| text[i..].chars().next())?,
+    })
+}
+
+/// Split a size into its number, with `decimal_separator` replaced by `.` and grouping
+/// removed, and its multiplier.
+fn split_size(text: &str, decimal_separator: char) -> Option<(String, u64)> {
+    let text = text.trim();
+    let split = text.find(|c: char| c.is_alphabetic()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+
+    let number = number
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .filter(|&c| c == decimal_separator || (c != ',' && c != '.'))
+        .map(|c| if c == decimal_separator { '.' } else { c })
+        .collect();
+
+    let multiplier: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" | "byte" | "bytes" => 1,
+        "kb" | "kib" => 1 << 10,
+        "mb" | "mib" => 1 << 20,
+        "gb" | "gib" => 1 << 30,
+        "tb" | "tib" => 1 << 40,
+        _ => return None,
+    };
+
+    Some((number, multiplier))
+}
+
+fn parse_size_with_separator(text: &str, decimal_separator: char) -> Option<u64> {
+    let (number, multiplier) = split_size(text, decimal_separator)?;
+    let number: f64 = number.parse().ok()?;
+
+    if !number.is_finite() || number < 0.0 {
+        return None;
+    }
+
+    Some((number * multiplier as f64).round() as u64)
+}
+
+#[cfg(feature = "time")]
+fn deserialize_date_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<time::OffsetDateTime, D::Error> {
+    let text = String::deserialize(deserializer)?;
+    parse_date_time(&text)
+        .ok_or_else(|| serde::de::Error::custom(format!("invalid date time {:?}", text)))
+}
+
+/// Parse ISO 8601 date times, e.g. `"2023-11-20T10:15:00+01:00"`. Date times without an offset
+/// are assumed to be Swedish local time.
+#[cfg(feature = "time")]
+pub(crate) fn parse_date_time(text: &str) -> Option<time::OffsetDateTime> {
+    use time::format_description::well_known::Iso8601;
+
+    if let Ok(date_time) = time::OffsetDateTime::parse(text, &Iso8601::DEFAULT) {
+        return Some(date_time);
+    }
+
+    let local = time::PrimitiveDateTime::parse(text, &Iso8601::DEFAULT).ok()?;
+    Some(local.assume_offset(stockholm_offset(local)))
+}
+
+/// UTC offset of Swedish local time: CEST (+02:00) from 02:00 on the last Sunday of March until
+/// 03:00 on the last Sunday of October, CET (+01:00) otherwise. Times repeated when the clocks go
+/// back are taken as CEST.
+#[cfg(feature = "time")]
+fn stockholm_offset(local: time::PrimitiveDateTime) -> time::UtcOffset {
+    let last_sunday = |month| {
+        let last = time::Date::from_calendar_date(local.year(), month, 31).ok()?;
+        last.checked_sub(time::Duration::days(
+            last.weekday().number_days_from_sunday() as i64,
+        ))
+    };
+    let summer = last_sunday(time::Month::March).zip(last_sunday(time::Month::October));
+
+    let is_summer = summer.is_some_and(|(start, end)| {
+        local >= start.with_hms(2, 0, 0).unwrap() && local < end.with_hms(3, 0, 0).unwrap()
+    });
+    let hours = if is_summer { 2 } else { 1 };
+
+    time::UtcOffset::from_hms(hours, 0, 0).unwrap()
+}
+
 /// File from a published Data Package.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DataPackageFile {
+    #[serde(alias = "IsFolder")]
     pub is_folder: bool,
+    #[serde(alias = "Name")]
     pub name: String,
-    pub size: String,
+    #[serde(alias = "Size", deserialize_with = "deserialize_file_size")]
+    pub size: FileSize,
 
     #[cfg(feature = "time")]
-    #[serde(with = "time::serde::iso8601")]
+    #[serde(
+        alias = "DateTime",
+        deserialize_with = "deserialize_date_time",
+        serialize_with = "time::serde::iso8601::serialize"
+    )]
     pub date_time: time::OffsetDateTime,
 
     #[cfg(not(feature = "time"))]
+    #[serde(alias = "DateTime")]
     pub date_time: String,
 
+    #[serde(alias = "Links")]
     pub links: Vec<FileLink>,
 }
 
+impl DataPackageFile {
+    /// Size in bytes, if the reported size could be parsed.
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.size.bytes()
+    }
+
+    /// Size in bytes, see [`FileSize::bytes_with_locale`].
+    pub fn size_bytes_with_locale(&self, decimal_separator: char) -> Option<u64> {
+        self.size.bytes_with_locale(decimal_separator)
+    }
+
+    /// The link whose `rel` marks it as the file's download link.
+    pub fn download_link(&self) -> Option<&FileLink> {
+        self.links
+            .iter()
+            .find(|link| link.rel.to_lowercase().contains("download"))
+    }
+
+    /// Whether the file is an actual file with a download link.
+    pub fn is_downloadable(&self) -> bool {
+        !self.is_folder && self.download_link().is_some()
+    }
+
+    /// Format of the file, going by its name.
+    pub fn format(&self) -> FileFormat {
+        self.name.parse().unwrap_or(FileFormat::Unknown)
+    }
+}
+
+/// Format of a data package file, see [`DataPackageFile::format`].
+///
+/// Parses format names (`"GeoPackage"`, `"shp"`) as well as file names, by their extension or
+/// format keyword, e.g. `Gävleborgs_län_Shape.zip`. Keywords only count as whole words, split
+/// on anything but letters and digits, so `Broar_Shapeinfo.zip` isn't a shapefile. Anything else
+/// is [`FileFormat::Unknown`].
+///
+/// ```rust
+/// # use lastkajen::types::FileFormat;
+/// assert_eq!("Gävleborgs_län_GeoPackage.zip".parse(), Ok(FileFormat::GeoPackage));
+/// assert_eq!("vägnät.gml".parse(), Ok(FileFormat::InspireGml));
+/// assert_eq!("readme.txt".parse(), Ok(FileFormat::Unknown));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum FileFormat {
+    GeoPackage,
+    Shape,
+    InspireGml,
+    Csv,
+    Unknown,
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::GeoPackage => "GeoPackage",
+            Self::Shape => "Shape",
+            Self::InspireGml => "INSPIRE-GML",
+            Self::Csv => "CSV",
+            Self::Unknown => "Unknown",
+        })
+    }
+}
+
+impl std::str::FromStr for FileFormat {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let name = name.to_lowercase();
+        let name = name.strip_suffix(".zip").unwrap_or(&name);
+        let words: Vec<&str> = name
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let has = |keywords: &[&str]| words.iter().any(|word| keywords.contains(word));
+
+        Ok(if has(&["geopackage", "gpkg"]) {
+            Self::GeoPackage
+        } else if has(&["shape", "shapefile", "shp"]) {
+            Self::Shape
+        } else if has(&["inspire", "gml"]) {
+            Self::InspireGml
+        } else if has(&["csv"]) {
+            Self::Csv
+        } else {
+            Self::Unknown
+        })
+    }
+}
+
 // -----------------------------------------------------
 
 /// User order information.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserFile {
+    #[serde(alias = "IsFolder")]
     pub is_folder: bool,
+    #[serde(alias = "Name")]
     pub name: String,
-    pub size: String,
+    #[serde(alias = "Size", deserialize_with = "deserialize_file_size")]
+    pub size: FileSize,
 
     // TODO: No time offset (ie timezone) is specified by the api result, figure
     // out workaround or a "good guess."
+    #[serde(alias = "DateTime")]
     pub date_time: String,
 }
 
+impl UserFile {
+    /// Size in bytes, if the reported size could be parsed.
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.size.bytes()
+    }
+
+    /// Size in bytes, see [`FileSize::bytes_with_locale`].
+    pub fn size_bytes_with_locale(&self, decimal_separator: char) -> Option<u64> {
+        self.size.bytes_with_locale(decimal_separator)
+    }
+}
+
+/// Summary of the user's orders, see
+/// [`Lastkajen::user_files_summary`](crate::Lastkajen::user_files_summary).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UserFilesSummary {
+    /// Number of (non-folder) files.
+    pub files: usize,
+    /// Bytes taken up by the user's files, counting only those with a readable size. A lower
+    /// bound whenever `unknown_sizes` isn't zero.
+    pub total_bytes: u64,
+    /// Number of files left out of `total_bytes` as their reported size couldn't be read.
+    pub unknown_sizes: usize,
+
+    /// Dates of the oldest and newest orders, in Swedish local time.
+    #[cfg(feature = "time")]
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub oldest: Option<time::OffsetDateTime>,
+    #[cfg(feature = "time")]
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub newest: Option<time::OffsetDateTime>,
+
+    /// Dates of the oldest and newest orders, as reported by the API.
+    #[cfg(not(feature = "time"))]
+    pub oldest: Option<String>,
+    #[cfg(not(feature = "time"))]
+    pub newest: Option<String>,
+
+    /// Number of files per order date, e.g. `"2023-11-20"`. Files with an unreadable date are
+    /// left out.
+    pub files_by_date: std::collections::BTreeMap<String, usize>,
+}
+
 // -----------------------------------------------------
 
+/// A saved download checked against its listing, see
+/// [`Lastkajen::download_package_file_verified`](crate::Lastkajen::download_package_file_verified).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadVerification {
+    pub path: std::path::PathBuf,
+    /// Size of the saved file in bytes.
+    pub bytes: u64,
+    /// Whether the saved size agrees with the listed one, see [`FileSize::matches`]. `None` if
+    /// the listed size couldn't be parsed.
+    pub size_matches: Option<bool>,
+}
+
+impl DownloadVerification {
+    /// Whether nothing contradicts the download being complete.
+    pub fn is_ok(&self) -> bool {
+        self.size_matches != Some(false)
+    }
+}
+
+// -----------------------------------------------------
+
+/// A listing element which couldn't be deserialized, see
+/// [`Lastkajen::get_published_packages_lenient`](crate::Lastkajen::get_published_packages_lenient).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeserializeIssue {
+    /// Position of the element in the listing.
+    pub index: usize,
+    pub error: String,
+}
+
+// -----------------------------------------------------
+
+/// Specification of a custom extract, see
+/// [`Lastkajen::create_user_order`](crate::Lastkajen::create_user_order).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderSpec {
+    /// Order parameters, as posted to the API.
+    pub parameters: serde_json::Value,
+}
+
+/// A created order, eventually available as the user file `file`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderHandle {
+    pub file: String,
+}
+
+// -----------------------------------------------------
+
+/// A file found by [`Lastkajen::search_files`](crate::Lastkajen::search_files).
+#[derive(Debug, Clone)]
+pub enum FoundFile {
+    Published(DataPackageFolder, DataPackageFile),
+    User(UserFile),
+}
+
+impl FoundFile {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Published(_, file) => &file.name,
+            Self::User(file) => &file.name,
+        }
+    }
+}
+
+// -----------------------------------------------------
+
+#[derive(Debug, Clone, Copy)]
 pub enum DownloadCategory<'a> {
     Published { id: &'a usize, file: &'a String },
     User { file: &'a String },
 }
 
+impl DownloadCategory<'_> {
+    pub fn kind(&self) -> PackageType {
+        match self {
+            Self::Published { .. } => PackageType::Published,
+            Self::User { .. } => PackageType::User,
+        }
+    }
+}
+
 pub enum DownloadToken {
     Published(String),
     User(String),
 }
+
+impl DownloadToken {
+    /// Which kind of file the token downloads. Tokens only work with their own kind.
+    pub fn category_kind(&self) -> PackageType {
+        match self {
+            Self::Published(_) => PackageType::Published,
+            Self::User(_) => PackageType::User,
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Format a byte count for display, e.g. `"1.2 GB"`, using the same binary multiples the API's
+/// sizes are parsed with, so that [`FileSize::parse`](crate::types::FileSize::parse) reads it
+/// back to within rounding.
+///
+/// ```rust
+/// # use lastkajen::util::format_bytes;
+/// # use lastkajen::types::FileSize;
+/// assert_eq!(format_bytes(512), "512 B");
+/// assert_eq!(format_bytes(1536), "1.5 kB");
+/// assert_eq!(format_bytes(1_288_490_189), "1.2 GB");
+/// assert_eq!(FileSize::parse(&format_bytes(1536)).bytes(), Some(1536));
+/// ```
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+
+    if bytes < 1 << 10 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64;
+    let mut unit = UNITS[0];
+    for candidate in UNITS {
+        size /= 1024.0;
+        unit = candidate;
+        // Move up a unit only if the rounded size would read 1024 or more.
+        if size < 1023.95 {
+            break;
+        }
+    }
+
+    format!("{:.1} {}", size, unit)
+}
+
+/// Content format told from a file's first bytes, see [`detect_format`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DetectedFormat {
+    Zip,
+    /// An SQLite database, which GeoPackages are.
+    GeoPackage,
+    /// XML, e.g. GML.
+    Xml,
+    /// An HTML page, likely an error or maintenance page rather than data.
+    Html,
+    Csv,
+    Unknown,
+}
+
+/// Callback told of downloads not in the format their name implies, see
+/// [`LastkajenBuilder::on_format_mismatch`](crate::LastkajenBuilder::on_format_mismatch).
+#[derive(Clone)]
+pub(crate) struct FormatMismatchHook(pub(crate) Arc<FormatMismatchFn>);
+
+type FormatMismatchFn = dyn Fn(&Path, DetectedFormat, DetectedFormat) + Send + Sync;
+
+impl FormatMismatchHook {
+    pub(crate) fn call(&self, path: &Path, expected: DetectedFormat, found: DetectedFormat) {
+        (self.0)(path, expected, found)
+    }
+}
+
+impl fmt::Debug for FormatMismatchHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FormatMismatchHook")
+    }
+}
+
+impl DetectedFormat {
+    /// The format a file name's extension implies, if it's one that can be detected.
+    pub fn from_extension(name: &str) -> Option<Self> {
+        let extension = std::path::Path::new(name).extension()?.to_str()?;
+
+        match extension.to_lowercase().as_str() {
+            "zip" => Some(Self::Zip),
+            "gpkg" | "sqlite" => Some(Self::GeoPackage),
+            "gml" | "xml" => Some(Self::Xml),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DetectedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Zip => "ZIP",
+            Self::GeoPackage => "GeoPackage",
+            Self::Xml => "XML",
+            Self::Html => "HTML",
+            Self::Csv => "CSV",
+            Self::Unknown => "unknown",
+        })
+    }
+}
+
+/// Tell the format of content from its first bytes, a few hundred of which are plenty.
+///
+/// ```rust
+/// # use lastkajen::util::{detect_format, DetectedFormat};
+/// assert_eq!(detect_format(b"PK\x03\x04\x14\x00"), DetectedFormat::Zip);
+/// assert_eq!(detect_format(b"<!DOCTYPE html><html>"), DetectedFormat::Html);
+/// assert_eq!(detect_format(b"id;namn\n1;Gavle\n"), DetectedFormat::Csv);
+/// ```
+pub fn detect_format(bytes: &[u8]) -> DetectedFormat {
+    if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+        return DetectedFormat::Zip;
+    }
+    if bytes.starts_with(b"SQLite format 3\0") {
+        return DetectedFormat::GeoPackage;
+    }
+
+    // Text from here on, cut off anywhere, so the last character may be incomplete.
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default()
+        }
+        Err(_) => return DetectedFormat::Unknown,
+    };
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.is_empty() || text.contains('\0') {
+        return DetectedFormat::Unknown;
+    }
+
+    let start: String = text.chars().take(64).collect::<String>().to_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return DetectedFormat::Html;
+    }
+    if text.starts_with('<') {
+        return DetectedFormat::Xml;
+    }
+
+    let first_line = text.lines().next().unwrap_or_default();
+    if first_line.contains([',', ';', '\t']) {
+        return DetectedFormat::Csv;
+    }
+
+    DetectedFormat::Unknown
+}
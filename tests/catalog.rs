@@ -0,0 +1,723 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use common::Response;
+use futures::TryStreamExt;
+use lastkajen::catalog::{diff_packages, find_package, CatalogCache, CatalogCursor};
+use lastkajen::types::{DataPackageFolder, FoundFile};
+use lastkajen::{Lastkajen, LastkajenError};
+
+fn package(id: usize, name: &str) -> DataPackageFolder {
+    serde_json::from_value(serde_json::json!({
+        "id": id,
+        "targetFolder": { "id": 1, "name": "Länsfiler", "path": "Länsfiler" },
+        "sourceFolder": format!("Datapaket\\Länsfiler NVDB-data\\{}", name),
+        "name": name,
+        "description": "",
+        "published": true,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn diff_reports_added_removed_and_changed() {
+    let old = [package(1, "Gävleborgs län"), package(2, "Uppsala län")];
+    let new = [package(2, "Uppsala län (ny)"), package(3, "Skåne län")];
+
+    let diff = diff_packages(&old, &new);
+
+    assert_eq!(diff.added, [package(3, "Skåne län")]);
+    assert_eq!(diff.removed, [package(1, "Gävleborgs län")]);
+    assert_eq!(diff.changed.len(), 1);
+    assert_eq!(diff.changed[0].old, package(2, "Uppsala län"));
+    assert_eq!(diff.changed[0].new, package(2, "Uppsala län (ny)"));
+}
+
+#[test]
+fn identical_listings_have_no_diff() {
+    let packages = [package(1, "Gävleborgs län")];
+
+    assert!(diff_packages(&packages, &packages).is_empty());
+}
+
+#[tokio::test]
+async fn counties_are_sorted_and_deduplicated() {
+    let mut nationwide = package(4, "Hela landet");
+    nationwide.source_folder = "Datapaket\\Riksfiler NVDB-data".into();
+    let mut nested = package(5, "Uppsala län");
+    nested.source_folder += "\\Vägnät";
+    let packages = [
+        package(3, "Uppsala län"),
+        package(1, "Gävleborgs län"),
+        nationwide,
+        nested,
+        package(6, "Hela landet"),
+    ];
+
+    let base_url =
+        common::serve_api(move |_| Response::json(&serde_json::to_string(&packages).unwrap()))
+            .await;
+    let api = common::client(&base_url).await;
+
+    let counties = api.list_counties().await.unwrap();
+
+    assert_eq!(counties, ["Gävleborgs län", "Uppsala län"]);
+}
+
+#[tokio::test]
+async fn search_matches_file_names_across_packages() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => Response::json(
+            &serde_json::to_string(&[package(1, "Gävleborgs län"), package(2, "Uppsala län")])
+                .unwrap(),
+        ),
+        "/api/file/GetUserFiles" => Response::json(
+            r#"[
+                { "isFolder": false, "name": "Beställning_GeoPackage.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": false, "name": "Beställning_Shape.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": true, "name": "GeoPackage", "size": "0 B", "dateTime": "2023-11-20T10:15:00" }
+            ]"#,
+        ),
+        path => {
+            let id = path.rsplit('/').next().unwrap();
+            Response::json(
+                &serde_json::json!([
+                    { "isFolder": true, "name": "GeoPackage", "size": "0 B", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                    { "isFolder": false, "name": format!("Län_{}_GeoPackage.zip", id), "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                    { "isFolder": false, "name": format!("Län_{}_Shape.zip", id), "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                ])
+                .to_string(),
+            )
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut found: Vec<_> = api
+        .search_files("geopackage")
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| match file {
+            FoundFile::Published(package, file) => (Some(package.id), file.name),
+            FoundFile::User(file) => (None, file.name),
+        })
+        .collect();
+    found.sort();
+
+    assert_eq!(
+        found,
+        [
+            (None, "Beställning_GeoPackage.zip".to_string()),
+            (Some(1), "Län_1_GeoPackage.zip".to_string()),
+            (Some(2), "Län_2_GeoPackage.zip".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn catalog_cache_round_trips() {
+    let path = common::temp_dir("catalog_cache").join("cache.json");
+    let cache = CatalogCache {
+        packages: vec![package(1, "Gävleborgs län")],
+        fetched_at: SystemTime::now(),
+    };
+
+    cache.save(&path).unwrap();
+
+    assert_eq!(CatalogCache::load(&path).unwrap(), cache);
+}
+
+#[tokio::test]
+async fn cached_listing_refetches_only_when_stale() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let base_url = common::serve_api(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Response::json(&serde_json::to_string(&[package(1, "Gävleborgs län")]).unwrap())
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let ttl = Duration::from_secs(60);
+
+    let mut cache = CatalogCache::default();
+    assert_eq!(
+        api.get_published_packages_cached(&mut cache, ttl)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        api.get_published_packages_cached(&mut cache, ttl)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    cache.fetched_at = SystemTime::now() - Duration::from_secs(120);
+    api.get_published_packages_cached(&mut cache, ttl)
+        .await
+        .unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn modified_since_filters_on_newest_file() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => Response::json(
+            &serde_json::to_string(&[package(1, "Gävleborgs län"), package(2, "Uppsala län")])
+                .unwrap(),
+        ),
+        path => {
+            let date_time = if path.ends_with("/1") {
+                "2023-11-20T10:15:00+01:00"
+            } else {
+                "2021-01-01T00:00:00+01:00"
+            };
+            Response::json(
+                &serde_json::json!([{
+                    "isFolder": false,
+                    "name": "file.zip",
+                    "size": "1 MB",
+                    "dateTime": date_time,
+                    "links": [],
+                }])
+                .to_string(),
+            )
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    // 2022-01-01T00:00:00Z
+    let since = time::OffsetDateTime::from_unix_timestamp(1_640_995_200).unwrap();
+    let packages = api.packages_modified_since(since).await.unwrap();
+
+    assert_eq!(packages, [package(1, "Gävleborgs län")]);
+}
+
+#[tokio::test]
+async fn sorted_listing_orders_by_id() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            &serde_json::to_string(&[package(3, "Skåne län"), package(1, "Gävleborgs län")])
+                .unwrap(),
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let packages = api.get_published_packages_sorted().await.unwrap();
+
+    assert_eq!(
+        packages,
+        [package(1, "Gävleborgs län"), package(3, "Skåne län")]
+    );
+}
+
+#[tokio::test]
+async fn all_downloadable_files_skips_folders() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => Response::json(
+            &serde_json::to_string(&[package(1, "Gävleborgs län"), package(2, "Uppsala län")])
+                .unwrap(),
+        ),
+        _ => Response::json(
+            &serde_json::json!([
+                { "isFolder": true, "name": "folder", "size": "0 B", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "file.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+            ])
+            .to_string(),
+        ),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut files: Vec<_> = api
+        .all_downloadable_files()
+        .map_ok(|(id, file)| (id, file.name))
+        .try_collect()
+        .await
+        .unwrap();
+    files.sort();
+
+    assert_eq!(
+        files,
+        [(1, "file.zip".to_string()), (2, "file.zip".to_string())]
+    );
+}
+
+#[tokio::test]
+async fn all_listings_are_fetched_together() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => {
+            Response::json(&serde_json::to_string(&[package(1, "Gävleborgs län")]).unwrap())
+        }
+        "/api/file/GetUserFiles" => Response::json(
+            r#"[{ "isFolder": false, "name": "order.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00" }]"#,
+        ),
+        _ => Response::new(404),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let (packages, files) = api.get_all_listings().await.unwrap();
+
+    assert_eq!(packages, [package(1, "Gävleborgs län")]);
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "order.zip");
+}
+
+#[tokio::test]
+async fn catalog_stats_sum_files_and_sizes() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => Response::json(
+            &serde_json::to_string(&[package(1, "Gävleborgs län"), package(2, "Uppsala län")])
+                .unwrap(),
+        ),
+        _ => Response::json(
+            &serde_json::json!([
+                { "isFolder": false, "name": "a.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "b.zip", "size": "unknown", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+            ])
+            .to_string(),
+        ),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let stats = api.catalog_stats().await.unwrap();
+
+    assert_eq!(stats.packages, 2);
+    assert_eq!(stats.packages_by_county.get("Uppsala län"), Some(&1));
+    assert_eq!(stats.files, 4);
+    assert_eq!(stats.total_bytes, 2 << 20);
+    assert_eq!(stats.unknown_sizes, 2);
+}
+
+#[tokio::test]
+async fn package_files_filter_by_extension() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            &serde_json::json!([
+                { "isFolder": false, "name": "Gävleborgs_län_GeoPackage.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "Gävleborgs_län_Shape.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": true, "name": "old_geopackage.zip", "size": "0 B", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+            ])
+            .to_string(),
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let files = api
+        .get_package_files_by_extension(&1, "_geopackage.ZIP")
+        .await
+        .unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "Gävleborgs_län_GeoPackage.zip");
+}
+
+#[cfg(feature = "csv")]
+#[tokio::test]
+async fn catalog_exports_as_csv() {
+    let base_url = common::serve_api(|_| {
+        Response::json(&serde_json::to_string(&[package(1, "Gävleborgs län, norra")]).unwrap())
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut csv = Vec::new();
+    api.export_catalog_csv(&mut csv).await.unwrap();
+
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        concat!(
+            "id,name,source_folder,county,published\n",
+            r#"1,"Gävleborgs län, norra","Datapaket\Länsfiler NVDB-data\Gävleborgs län, norra",,true"#,
+            "\n",
+        )
+    );
+}
+
+#[tokio::test]
+async fn target_folders_are_deduplicated() {
+    let mut other = package(3, "Övrigt");
+    other.target_folder.id = 2;
+    other.target_folder.path = "Andra".into();
+    let packages = [
+        package(1, "Gävleborgs län"),
+        package(2, "Uppsala län"),
+        other,
+    ];
+
+    let base_url =
+        common::serve_api(move |_| Response::json(&serde_json::to_string(&packages).unwrap()))
+            .await;
+    let api = common::client(&base_url).await;
+
+    let folders = api.list_target_folders().await.unwrap();
+
+    let ids: Vec<usize> = folders.iter().map(|folder| folder.id).collect();
+    assert_eq!(ids, [2, 1]);
+}
+
+#[tokio::test]
+async fn packages_are_listed_by_target_path() {
+    let mut nested = package(2, "Uppsala län");
+    nested.target_folder.path = "Länsfiler\\Uppsala".into();
+    let mut sibling = package(3, "Övrigt");
+    sibling.target_folder.path = "Länsfiler2".into();
+    let packages = [package(1, "Gävleborgs län"), nested, sibling];
+
+    let base_url =
+        common::serve_api(move |_| Response::json(&serde_json::to_string(&packages).unwrap()))
+            .await;
+    let api = common::client(&base_url).await;
+
+    let ids = |packages: Vec<DataPackageFolder>| -> Vec<usize> {
+        packages.iter().map(|package| package.id).collect()
+    };
+    assert_eq!(
+        ids(api.packages_under_path("länsfiler/").await.unwrap()),
+        [1, 2]
+    );
+    assert_eq!(
+        ids(api.packages_under_path("LÄNSFILER\\uppsala").await.unwrap()),
+        [2]
+    );
+    assert_eq!(ids(api.packages_under_path("").await.unwrap()), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn package_files_are_cached_until_cleared() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let base_url = common::serve_api(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Response::json("[]")
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let ttl = Duration::from_secs(60);
+
+    api.get_package_files_cached(&1, ttl).await.unwrap();
+    api.get_package_files_cached(&1, ttl).await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    api.get_package_files_cached(&2, ttl).await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+    api.clear_cache();
+    api.get_package_files_cached(&1, ttl).await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn find_package_rejects_ambiguous_matches() {
+    let packages = [
+        package(1, "Gävleborgs län"),
+        package(2, "Uppsala län"),
+        package(3, "Uppsala län"),
+    ];
+
+    assert_eq!(find_package(&packages, |p| p.id == 1).unwrap().id, 1);
+    assert!(matches!(
+        find_package(&packages, |p| p.name == "Uppsala län"),
+        Err(LastkajenError::Ambiguous(_))
+    ));
+    assert!(matches!(
+        find_package(&packages, |p| p.name == "Skåne län"),
+        Err(LastkajenError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn user_files_are_summarized() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            r#"[
+                { "isFolder": false, "name": "a.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": false, "name": "b.zip", "size": "2 kB", "dateTime": "2023-11-20T08:00:00" },
+                { "isFolder": false, "name": "c.zip", "size": "n/a", "dateTime": "2023-12-01T12:00:00" },
+                { "isFolder": true, "name": "folder", "size": "0 B", "dateTime": "2023-10-01T12:00:00" }
+            ]"#,
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let summary = api.user_files_summary().await.unwrap();
+
+    assert_eq!(summary.files, 3);
+    assert_eq!(summary.total_bytes, 3072);
+    assert_eq!(summary.unknown_sizes, 1);
+    assert_eq!(
+        summary.files_by_date.into_iter().collect::<Vec<_>>(),
+        [("2023-11-20".to_string(), 2), ("2023-12-01".to_string(), 1)]
+    );
+
+    // Swedish winter time, so an hour ahead of UTC.
+    #[cfg(feature = "time")]
+    {
+        let at = |timestamp| time::OffsetDateTime::from_unix_timestamp(timestamp).ok();
+        assert_eq!(summary.oldest, at(1_700_463_600));
+        assert_eq!(summary.newest, at(1_701_428_400));
+    }
+    #[cfg(not(feature = "time"))]
+    assert_eq!(summary.newest.as_deref(), Some("2023-12-01T12:00:00"));
+}
+
+#[tokio::test]
+async fn sync_plan_compares_local_files() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            r#"[
+                { "isFolder": false, "name": "same.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "changed.zip", "size": "2 kB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "missing.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": true, "name": "folder", "size": "0 B", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] }
+            ]"#,
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let dir = common::temp_dir("sync-plan");
+    std::fs::write(dir.join("same.zip"), vec![0; 1024]).unwrap();
+    std::fs::write(dir.join("changed.zip"), vec![0; 10]).unwrap();
+    std::fs::write(dir.join("old.zip"), b"old").unwrap();
+    std::fs::write(dir.join("missing.zip.part"), b"partial").unwrap();
+
+    let plan = api.plan_sync(7, &dir).await.unwrap();
+    let names = |files: &[lastkajen::types::DataPackageFile]| {
+        files.iter().map(|f| f.name.clone()).collect::<Vec<_>>()
+    };
+
+    assert_eq!(plan.package_id, 7);
+    assert_eq!(names(&plan.up_to_date), ["same.zip"]);
+    assert_eq!(names(&plan.to_download), ["changed.zip", "missing.zip"]);
+    assert_eq!(plan.extra, ["old.zip"]);
+    assert!(!plan.is_up_to_date());
+
+    let plan = api.plan_sync(7, dir.join("nowhere")).await.unwrap();
+    assert_eq!(plan.to_download.len(), 3);
+    assert!(plan.extra.is_empty());
+}
+
+#[tokio::test]
+async fn refreshed_file_has_current_links() {
+    let listings = Arc::new(AtomicUsize::new(0));
+    let counter = listings.clone();
+    let base_url = common::serve_api(move |_| {
+        let listing = counter.fetch_add(1, Ordering::SeqCst);
+        Response::json(
+            &serde_json::json!([
+                { "isFolder": false, "name": "other.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                { "isFolder": false, "name": "file.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [
+                    { "rel": "download", "href": format!("/file.zip?v={}", listing), "method": "GET", "isTemplated": false }
+                ] },
+            ])
+            .to_string(),
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let first = api.refresh_file(&1, "file.zip").await.unwrap();
+    let second = api.refresh_file(&1, "file.zip").await.unwrap();
+    assert_eq!(first.download_link().unwrap().href, "/file.zip?v=0");
+    assert_eq!(second.download_link().unwrap().href, "/file.zip?v=1");
+
+    assert!(matches!(
+        api.refresh_file(&1, "gone.zip").await,
+        Err(LastkajenError::NotFound(_))
+    ));
+}
+
+#[tokio::test]
+async fn catalog_walk_resumes_from_cursor() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/DataPackage/GetPublishedDataPackages" => Response::json(
+            &serde_json::to_string(&[
+                package(1, "Gävleborgs län"),
+                package(2, "Uppsala län"),
+                package(3, "Skåne län"),
+            ])
+            .unwrap(),
+        ),
+        path => {
+            let id = path.rsplit('/').next().unwrap();
+            Response::json(
+                &serde_json::json!([
+                    { "isFolder": false, "name": format!("{}_a.zip", id), "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                    { "isFolder": false, "name": format!("{}_b.zip", id), "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                ])
+                .to_string(),
+            )
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let cursor = CatalogCursor::new();
+    cursor.complete(1);
+
+    // Interrupted in the middle of a package, which is then left incomplete.
+    let (interrupted, _) = {
+        let files = api.all_downloadable_files_from(cursor.clone());
+        futures::pin_mut!(files);
+        files.try_next().await.unwrap().unwrap()
+    };
+    assert_ne!(interrupted, 1);
+    assert_eq!(cursor.completed(), [1]);
+
+    let path = common::temp_dir("catalog_cursor").join("cursor.json");
+    cursor.save(&path).unwrap();
+    let cursor = CatalogCursor::load(&path).unwrap();
+
+    let mut files: Vec<_> = api
+        .all_downloadable_files_from(cursor.clone())
+        .map_ok(|(_, file)| file.name)
+        .try_collect()
+        .await
+        .unwrap();
+    files.sort();
+
+    assert_eq!(files, ["2_a.zip", "2_b.zip", "3_a.zip", "3_b.zip"]);
+    assert_eq!(cursor.completed(), [1, 2, 3]);
+}
+
+#[tokio::test]
+async fn only_unseen_user_files_are_new() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            r#"[
+                { "isFolder": false, "name": "a.zip", "size": "1 kB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": false, "name": "b.zip", "size": "2 kB", "dateTime": "2023-11-20T08:00:00" }
+            ]"#,
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut seen = std::collections::HashSet::from(["a.zip".to_string(), "old.zip".to_string()]);
+    let new_files = api.new_user_files_since(&seen).await.unwrap();
+    assert_eq!(new_files.len(), 1);
+    assert_eq!(new_files[0].name, "b.zip");
+
+    seen.extend(new_files.into_iter().map(|file| file.name));
+    assert!(api.new_user_files_since(&seen).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unknown_packages_are_not_found() {
+    let mut unpublished = package(2, "Uppsala län");
+    unpublished.published = false;
+    let listing = serde_json::to_string(&[package(1, "Gävleborgs län"), unpublished]).unwrap();
+    let base_url = common::serve_api(move |_| Response::json(&listing)).await;
+    let api = common::client(&base_url).await;
+
+    assert!(api.is_package_published(&1).await.unwrap());
+    assert!(!api.is_package_published(&2).await.unwrap());
+    assert!(matches!(
+        api.is_package_published(&99).await,
+        Err(LastkajenError::NotFound(message)) if message.contains("99")
+    ));
+}
+
+#[tokio::test]
+async fn packages_are_grouped_by_target_folder() {
+    let mut national = package(3, "Sverige");
+    national.target_folder.id = 2;
+    national.target_folder.name = "Riksfiler".into();
+    let listing = serde_json::to_string(&[
+        package(1, "Gävleborgs län"),
+        national,
+        package(2, "Uppsala län"),
+    ])
+    .unwrap();
+    let base_url = common::serve_api(move |_| Response::json(&listing)).await;
+    let api = common::client(&base_url).await;
+
+    let grouped = api.get_published_packages_grouped().await.unwrap();
+
+    let groups: Vec<_> = grouped
+        .iter()
+        .map(|(id, (folder, packages))| {
+            let ids: Vec<_> = packages.iter().map(|package| package.id).collect();
+            (*id, folder.name.as_str(), ids)
+        })
+        .collect();
+    assert_eq!(
+        groups,
+        [(1, "Länsfiler", vec![1, 2]), (2, "Riksfiler", vec![3])]
+    );
+}
+
+#[tokio::test]
+async fn unpublished_packages_are_left_out_of_visible_listing() {
+    let mut unpublished = package(2, "Uppsala län");
+    unpublished.published = false;
+    let listing = serde_json::to_string(&[
+        package(1, "Gävleborgs län"),
+        unpublished,
+        package(3, "Skåne län"),
+    ])
+    .unwrap();
+    let base_url = common::serve_api(move |_| Response::json(&listing)).await;
+    let api = common::client(&base_url).await;
+
+    assert_eq!(api.get_published_packages().await.unwrap().len(), 3);
+    assert_eq!(
+        api.get_published_packages_visible().await.unwrap(),
+        [package(1, "Gävleborgs län"), package(3, "Skåne län")]
+    );
+}
+
+#[tokio::test]
+async fn latest_package_file_skips_folders() {
+    let base_url = common::serve_api(|req| {
+        if req.path.ends_with("/2") {
+            return Response::json("[]");
+        }
+        Response::json(
+            &serde_json::json!([
+                { "isFolder": false, "name": "old.zip", "size": "1 MB", "dateTime": "2021-01-01T00:00:00+01:00", "links": [] },
+                { "isFolder": true, "name": "folder", "size": "0 B", "dateTime": "2024-01-01T00:00:00+01:00", "links": [] },
+                { "isFolder": false, "name": "new.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+            ])
+            .to_string(),
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let latest = api.latest_package_file(&1).await.unwrap().unwrap();
+    assert_eq!(latest.name, "new.zip");
+    assert_eq!(api.latest_package_file(&2).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn catalog_is_fetched_over_the_login_connection() {
+    let listing = serde_json::to_string(&[package(1, "Gävleborgs län")]).unwrap();
+    let (base_url, connections) = common::serve_keep_alive(move |_| Response::json(&listing)).await;
+
+    let (api, packages) = Lastkajen::builder()
+        .base_url(&base_url)
+        .build_with_catalog("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert_eq!(packages, [package(1, "Gävleborgs län")]);
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    assert!(api.ping().await.is_ok());
+}
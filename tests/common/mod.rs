@@ -0,0 +1,307 @@
+//! Minimal HTTP server standing in for the Lastkajen API.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use lastkajen::Lastkajen;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const LOGIN_PATH: &str = "/api/Identity/Login";
+pub const TOKEN: &str = r#"{"access_token":"token","expires_in":3600,"is_external":false}"#;
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn json(body: &str) -> Self {
+        Self::new(200)
+            .header("Content-Type", "application/json")
+            .body(body)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Serve `handler` on a random local port, returning the base url.
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move { handle(stream, handler.as_ref()).await });
+        }
+    });
+
+    base_url
+}
+
+/// Like [`serve`], answering logins with [`TOKEN`].
+pub async fn serve_api<F>(handler: F) -> String
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    serve(move |req| match req.path.as_str() {
+        LOGIN_PATH => Response::json(TOKEN),
+        _ => handler(req),
+    })
+    .await
+}
+
+/// Client logged in against the server at `base_url`.
+pub async fn client(base_url: &str) -> Lastkajen {
+    Lastkajen::builder()
+        .base_url(base_url)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap()
+}
+
+/// Fresh, empty directory under the system temp dir.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lastkajen-{}", name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Like [`serve_api`], but keeping connections alive between requests. Returns the base url and
+/// the number of connections accepted so far.
+pub async fn serve_keep_alive<F>(handler: F) -> (String, Arc<AtomicUsize>)
+where
+    F: Fn(&Request) -> Response + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let handler = Arc::new(handler);
+    let connections = Arc::new(AtomicUsize::new(0));
+
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            accepted.fetch_add(1, Ordering::SeqCst);
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                while let Some(request) = read_request(&mut stream).await {
+                    let response = match request.path.as_str() {
+                        LOGIN_PATH => Response::json(TOKEN),
+                        _ => handler(&request),
+                    };
+
+                    if write_response(&mut stream, &response, true).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (base_url, connections)
+}
+
+/// Serve logins with [`TOKEN`] and any other request with `len` zero bytes, written in small
+/// chunks and counted into `written` as they're accepted by the socket.
+pub async fn serve_streaming(len: usize, written: Arc<AtomicUsize>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let written = written.clone();
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                if request.path == LOGIN_PATH {
+                    let _ = write_response(&mut stream, &Response::json(TOKEN), false).await;
+                    return;
+                }
+
+                let head = Response::new(200);
+                if write_head(&mut stream, &head, len as u64, false)
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                let chunk = [0u8; 16 * 1024];
+                let mut remaining = len;
+                while remaining > 0 {
+                    let size = remaining.min(chunk.len());
+                    if stream.write_all(&chunk[..size]).await.is_err() {
+                        return;
+                    }
+                    written.fetch_add(size, Ordering::SeqCst);
+                    remaining -= size;
+                }
+            });
+        }
+    });
+
+    base_url
+}
+
+/// Serve logins with [`TOKEN`] and any other request with a response claiming `len` bytes, of
+/// which only a single byte is sent after `delay`.
+pub async fn serve_stalling(len: u64, delay: std::time::Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                if request.path == LOGIN_PATH {
+                    let _ = write_response(&mut stream, &Response::json(TOKEN), false).await;
+                } else if write_head(&mut stream, &Response::new(200), len, false)
+                    .await
+                    .is_ok()
+                {
+                    tokio::time::sleep(delay).await;
+                    let _ = stream.write_all(b"\0").await;
+                }
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+
+    base_url
+}
+
+async fn handle<F>(mut stream: TcpStream, handler: &F)
+where
+    F: Fn(&Request) -> Response,
+{
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let response = handler(&request);
+
+    let _ = write_response(&mut stream, &response, false).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Write `response`, head and body, closing the connection after it unless `keep_alive`.
+async fn write_response(
+    stream: &mut TcpStream,
+    response: &Response,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    write_head(stream, response, response.body.len() as u64, keep_alive).await?;
+    stream.write_all(&response.body).await
+}
+
+/// Write the status line and headers of `response`, announcing a body of `length` bytes.
+async fn write_head(
+    stream: &mut TcpStream,
+    response: &Response,
+    length: u64,
+    keep_alive: bool,
+) -> std::io::Result<()> {
+    let mut head = format!("HTTP/1.1 {} Mock\r\n", response.status);
+    if !keep_alive {
+        head += "Connection: close\r\n";
+    }
+    // A handler setting Transfer-Encoding frames the body itself.
+    let framed = response
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"));
+    if response.status != 204 && !framed {
+        head += &format!("Content-Length: {}\r\n", length);
+    }
+    for (name, value) in &response.headers {
+        head += &format!("{}: {}\r\n", name, value);
+    }
+    head += "\r\n";
+
+    stream.write_all(head.as_bytes()).await
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0u8; 1024];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0);
+    let mut body = buffer[header_end + 4..].to_vec();
+    while body.len() < length {
+        let mut chunk = [0u8; 1024];
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+
+    Some(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
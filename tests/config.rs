@@ -0,0 +1,58 @@
+#![cfg(all(feature = "config", unix))]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use common::Response;
+use lastkajen::Lastkajen;
+
+fn write_config(name: &str, contents: &str, mode: u32) -> std::path::PathBuf {
+    let dir = common::temp_dir(name);
+    let path = dir.join("lastkajen.json");
+    fs::write(&path, contents).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn config_logs_in_against_base_url() {
+    let base_url = common::serve_api(|_| Response::json("[]")).await;
+    let path = write_config(
+        "config",
+        &format!(
+            r#"{{ "username": "user", "password": "password", "base_url": "{}" }}"#,
+            base_url
+        ),
+        0o600,
+    );
+
+    let api = Lastkajen::from_config(&path).await.unwrap();
+
+    assert_eq!(api.token().unwrap().access_token, "token");
+}
+
+#[tokio::test]
+async fn readable_config_is_refused() {
+    let path = write_config(
+        "readable-config",
+        r#"{ "username": "user", "password": "password" }"#,
+        0o644,
+    );
+
+    assert!(Lastkajen::from_config(&path).await.is_err());
+}
+
+#[tokio::test]
+async fn config_errors_leave_out_contents() {
+    let path = write_config(
+        "invalid-config",
+        r#"{ "username": "user", "password": 12345678 }"#,
+        0o600,
+    );
+
+    let err = Lastkajen::from_config(&path).await.unwrap_err();
+
+    assert!(!err.to_string().contains("12345678"));
+}
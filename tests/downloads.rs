@@ -0,0 +1,1309 @@
+mod common;
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::Response;
+use futures::TryStreamExt;
+use lastkajen::progress::CheckpointInterval;
+use lastkajen::sink::DownloadSink;
+use lastkajen::types::{
+    DataPackageFile, DataPackageFolder, DownloadCategory, DownloadToken, FileSize,
+};
+use lastkajen::util::DetectedFormat;
+use lastkajen::{Lastkajen, LastkajenError};
+
+/// Writer that only keeps what has been flushed.
+#[derive(Default)]
+struct FlushedOnly {
+    pending: Vec<u8>,
+    flushed: Vec<u8>,
+}
+
+impl Write for FlushedOnly {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flushed.append(&mut self.pending);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn download_flushes_writer() {
+    let base_url = common::serve_api(|_| Response::new(200).body("file contents")).await;
+    let api = common::client(&base_url).await;
+
+    let mut writer = FlushedOnly::default();
+    api.download_with_token(DownloadToken::User("dltoken".into()), &mut writer)
+        .await
+        .unwrap();
+
+    assert_eq!(writer.flushed, b"file contents");
+}
+
+#[tokio::test]
+async fn throttled_downloads_keep_to_the_rate() {
+    const RATE: u64 = 256 * 1024;
+    let base_url = common::serve_api(|_| Response::new(200).body(vec![0u8; RATE as usize])).await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_bytes_per_sec(RATE)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    // A second's worth of data, give or take scheduling and the mock's own overhead.
+    let expected = Duration::from_secs(1);
+    let tolerance = Duration::from_millis(500);
+
+    let started = Instant::now();
+    api.download_with_token(DownloadToken::User("dltoken".into()), &mut io::sink())
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= expected - Duration::from_millis(50) && elapsed < expected + tolerance,
+        "{:?}",
+        elapsed
+    );
+
+    let started = Instant::now();
+    let chunks: Vec<_> = api
+        .download_stream(DownloadToken::User("dltoken".into()))
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    let elapsed = started.elapsed();
+    assert_eq!(
+        chunks.iter().map(|chunk| chunk.len()).sum::<usize>(),
+        RATE as usize
+    );
+    assert!(
+        elapsed >= expected - Duration::from_millis(50) && elapsed < expected + tolerance,
+        "{:?}",
+        elapsed
+    );
+}
+
+#[tokio::test]
+async fn download_to_path_renames_complete_file() {
+    let base_url = common::serve_api(|_| Response::json(r#""dltoken""#)).await;
+    let api = common::client(&base_url).await;
+
+    let dir = common::temp_dir("download-to-path");
+    let path = dir.join("file.zip");
+
+    let file = "file.zip".to_string();
+    api.download_to_path(DownloadCategory::User { file: &file }, &path)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), br#""dltoken""#);
+    assert!(!dir.join("file.zip.part").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn download_to_path_stages_in_staging_dir() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body(vec![0u8; 512 << 10])
+        }
+    })
+    .await;
+    let staging = common::temp_dir("staging-dir");
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .staging_dir(Some(staging.clone()))
+        .max_bytes_per_sec(1 << 20)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let dir = common::temp_dir("staged-download-to-path");
+    let path = dir.join("file.zip");
+
+    let file = "file.zip".to_string();
+    let download = api.download_to_path(DownloadCategory::User { file: &file }, &path);
+    let check = async {
+        // Throttled, the download takes about half a second.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let staged: Vec<_> = std::fs::read_dir(&staging)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert_eq!(staged.len(), 1);
+        assert!(staged[0].starts_with("file.zip.") && staged[0].ends_with(".part"));
+        assert!(!dir.join("file.zip.part").exists());
+    };
+    let (result, ()) = futures::join!(download, check);
+    result.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap().len(), 512 << 10);
+    assert_eq!(std::fs::read_dir(&staging).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&staging).unwrap();
+}
+
+#[tokio::test]
+async fn same_named_downloads_to_different_dirs_are_staged_apart() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = if req.path.contains("first") { "a" } else { "b" };
+            Response::json(&format!(r#""{}""#, token))
+        } else if req.path.ends_with("token=a") {
+            Response::new(200).body(vec![b'a'; 256 << 10])
+        } else {
+            Response::new(200).body(vec![b'b'; 256 << 10])
+        }
+    })
+    .await;
+    let staging = common::temp_dir("shared-staging-dir");
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .staging_dir(Some(staging.clone()))
+        .max_bytes_per_sec(1 << 20)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let first_dir = common::temp_dir("staged-first");
+    let second_dir = common::temp_dir("staged-second");
+    let first = "first".to_string();
+    let second = "second".to_string();
+    let (first_result, second_result) = futures::join!(
+        api.download_to_path(
+            DownloadCategory::User { file: &first },
+            first_dir.join("file.zip")
+        ),
+        api.download_to_path(
+            DownloadCategory::User { file: &second },
+            second_dir.join("file.zip")
+        ),
+    );
+    first_result.unwrap();
+    second_result.unwrap();
+
+    assert_eq!(
+        std::fs::read(first_dir.join("file.zip")).unwrap(),
+        vec![b'a'; 256 << 10]
+    );
+    assert_eq!(
+        std::fs::read(second_dir.join("file.zip")).unwrap(),
+        vec![b'b'; 256 << 10]
+    );
+    assert_eq!(std::fs::read_dir(&staging).unwrap().count(), 0);
+    std::fs::remove_dir_all(&first_dir).unwrap();
+    std::fs::remove_dir_all(&second_dir).unwrap();
+    std::fs::remove_dir_all(&staging).unwrap();
+}
+
+#[tokio::test]
+async fn download_to_path_checks_content_format() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = if req.path.contains("broken") {
+                "broken"
+            } else {
+                "a"
+            };
+            Response::json(&format!(r#""{}""#, token))
+        } else if req.path.ends_with("token=broken") {
+            Response::new(200).body("<!DOCTYPE html><html><body>Error</body></html>")
+        } else {
+            Response::new(200).body(b"PK\x03\x04rest of the archive".to_vec())
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .check_format(true)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    let dir = common::temp_dir("checked-format");
+
+    let file = "file.zip".to_string();
+    api.download_to_path(DownloadCategory::User { file: &file }, dir.join(&file))
+        .await
+        .unwrap();
+    assert!(dir.join(&file).exists());
+
+    let broken = "broken.zip".to_string();
+    let result = api
+        .download_to_path(DownloadCategory::User { file: &broken }, dir.join(&broken))
+        .await;
+    assert!(matches!(
+        result,
+        Err(LastkajenError::FormatMismatch(
+            DetectedFormat::Zip,
+            DetectedFormat::Html
+        ))
+    ));
+    assert!(!dir.join(&broken).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn format_mismatches_can_be_warned_about() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body("<!DOCTYPE html><html><body>Error</body></html>")
+        }
+    })
+    .await;
+    let warnings = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let collected = warnings.clone();
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .on_format_mismatch(move |path, expected, found| {
+            collected
+                .lock()
+                .unwrap()
+                .push((path.to_path_buf(), expected, found))
+        })
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    let dir = common::temp_dir("warned-format");
+
+    let file = "file.zip".to_string();
+    api.download_to_path(DownloadCategory::User { file: &file }, dir.join(&file))
+        .await
+        .unwrap();
+
+    assert!(dir.join(&file).exists());
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        [(dir.join(&file), DetectedFormat::Zip, DetectedFormat::Html)]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn failed_download_to_path_leaves_no_file() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetFileStream") {
+            Response::new(500).body("broken")
+        } else {
+            Response::json(r#""dltoken""#)
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let dir = common::temp_dir("failed-download-to-path");
+    let path = dir.join("file.zip");
+
+    let file = "file.zip".to_string();
+    let result = api
+        .download_to_path(DownloadCategory::User { file: &file }, &path)
+        .await;
+
+    assert!(result.is_err());
+    assert!(!path.exists());
+    assert!(!dir.join("file.zip.part").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn downloads_stop_at_the_byte_budget() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/file/GetFileStream?token=announced" => Response::new(200).body(vec![0u8; 4096]),
+        // Chunked, so the size is only known as it arrives.
+        _ => {
+            let chunk = format!("800\r\n{}\r\n", "x".repeat(0x800));
+            Response::new(200)
+                .header("Transfer-Encoding", "chunked")
+                .body(format!("{}{}0\r\n\r\n", chunk, chunk))
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url.clone())
+        .max_download_bytes(3000)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let mut written = Vec::new();
+    let result = api
+        .download_with_token(DownloadToken::User("announced".into()), &mut written)
+        .await;
+    assert!(matches!(
+        result,
+        Err(LastkajenError::DownloadTooLarge(3000))
+    ));
+    assert!(written.is_empty());
+
+    let result = api
+        .download_with_token(DownloadToken::User("chunked".into()), &mut written)
+        .await;
+    assert!(matches!(
+        result,
+        Err(LastkajenError::DownloadTooLarge(3000))
+    ));
+    assert!(written.len() <= 3000);
+
+    let mut written = Vec::new();
+    Lastkajen::builder()
+        .base_url(base_url)
+        .max_download_bytes(4096)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap()
+        .download_with_token(DownloadToken::User("chunked".into()), &mut written)
+        .await
+        .unwrap();
+    assert_eq!(written.len(), 4096);
+}
+
+/// Sink standing in for an object store upload, committed on finalize.
+#[derive(Default)]
+struct UploadSink {
+    parts: Vec<Vec<u8>>,
+    committed: Arc<std::sync::Mutex<Option<Vec<u8>>>>,
+}
+
+impl DownloadSink for UploadSink {
+    fn write_chunk(&mut self, chunk: &[u8]) -> lastkajen::Result<()> {
+        self.parts.push(chunk.to_vec());
+        Ok(())
+    }
+
+    fn finalize(self) -> lastkajen::Result<()> {
+        *self.committed.lock().unwrap() = Some(self.parts.concat());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn downloads_go_to_custom_sinks() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/file/GetFileStream?token=ok" => Response::new(200).body(vec![7u8; 4096]),
+        _ => Response::new(500),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let sink = UploadSink::default();
+    let committed = sink.committed.clone();
+    api.download_with_token(DownloadToken::User("ok".into()), sink)
+        .await
+        .unwrap();
+    assert_eq!(committed.lock().unwrap().as_deref(), Some(&[7u8; 4096][..]));
+
+    let sink = UploadSink::default();
+    let committed = sink.committed.clone();
+    assert!(api
+        .download_with_token(DownloadToken::User("broken".into()), sink)
+        .await
+        .is_err());
+    assert!(committed.lock().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn dropped_download_to_path_leaves_no_part_file() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetFileStream") {
+            Response::new(200).body(vec![0u8; 1 << 20])
+        } else {
+            Response::json(r#""dltoken""#)
+        }
+    })
+    .await;
+    // Slow enough that the download is still going when it's dropped.
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_bytes_per_sec(64 * 1024)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let dir = common::temp_dir("dropped-download-to-path");
+    let path = dir.join("file.zip");
+    let file = "file.zip".to_string();
+
+    let mut download =
+        Box::pin(api.download_to_path(DownloadCategory::User { file: &file }, &path));
+    assert!(
+        tokio::time::timeout(Duration::from_millis(300), &mut download)
+            .await
+            .is_err()
+    );
+    assert!(dir.join("file.zip.part").exists());
+
+    drop(download);
+    assert!(!dir.join("file.zip.part").exists());
+    assert!(!path.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn ranges_are_downloaded_or_refused() {
+    const FILE: &[u8] = b"SQLite format 3\0 and the rest of the GeoPackage";
+
+    let base_url = common::serve_api(|req| {
+        if let Some(file) = req
+            .path
+            .strip_prefix("/api/file/GetUserFileDownloadToken?fileName=")
+        {
+            return Response::json(&format!(r#""{}""#, file));
+        }
+        let range = req.headers.get("range").and_then(|range| {
+            let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+            Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+        });
+        match range {
+            Some((start, end)) if req.path.ends_with("token=ranged.gpkg") => Response::new(206)
+                .header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, end, FILE.len()),
+                )
+                .body(&FILE[start..=end]),
+            _ => Response::new(200).body(FILE),
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let file = "ranged.gpkg".to_string();
+    let mut header = Vec::new();
+    api.download_range(DownloadCategory::User { file: &file }, 0, 15, &mut header)
+        .await
+        .unwrap();
+    assert_eq!(header, b"SQLite format 3\0");
+
+    let file = "plain.gpkg".to_string();
+    let mut written = Vec::new();
+    let result = api
+        .download_range(DownloadCategory::User { file: &file }, 0, 15, &mut written)
+        .await;
+    assert!(matches!(result, Err(LastkajenError::RangeNotSupported)));
+    assert!(written.is_empty());
+}
+
+#[tokio::test]
+async fn download_reports_progress() {
+    let base_url = common::serve_api(|_| Response::new(200).body(vec![0u8; 4096])).await;
+    let api = common::client(&base_url).await;
+
+    let mut reports = Vec::new();
+    api.download_with_progress(
+        DownloadToken::User("dltoken".into()),
+        &mut io::sink(),
+        |progress| reports.push(progress.clone()),
+    )
+    .await
+    .unwrap();
+
+    let last = reports.last().unwrap();
+    assert_eq!(last.bytes_done, 4096);
+    assert_eq!(last.total, Some(4096));
+    assert_eq!(last.fraction(), Some(1.0));
+}
+
+#[tokio::test]
+async fn progress_of_a_huge_download_at_a_crawl_does_not_panic() {
+    // The largest length hyper accepts, trickling in at well under a byte per second.
+    let total = u64::MAX - 2;
+    let base_url = common::serve_stalling(total, Duration::from_millis(1500)).await;
+    let api = common::client(&base_url).await;
+
+    let mut reports = Vec::new();
+    let result = api
+        .download_with_progress(
+            DownloadToken::User("dltoken".into()),
+            &mut io::sink(),
+            |progress| reports.push(progress.clone()),
+        )
+        .await;
+
+    // The server hangs up early, but only after progress on the first byte was reported.
+    assert!(result.is_err());
+    let first = reports.first().unwrap();
+    assert_eq!(first.bytes_done, 1);
+    assert_eq!(first.total, Some(total));
+    assert!(first.bytes_per_sec < 1.0);
+    assert_eq!(first.eta, None);
+}
+
+#[cfg(feature = "checksum")]
+#[tokio::test]
+async fn download_hashing_returns_sha256() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetFileStream") {
+            Response::new(200).body("abc")
+        } else {
+            Response::json(r#""dltoken""#)
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let file = "file.zip".to_string();
+    let mut contents = Vec::new();
+    let (bytes, digest) = api
+        .download_hashing(DownloadCategory::User { file: &file }, &mut contents)
+        .await
+        .unwrap();
+
+    assert_eq!(bytes, 3);
+    assert_eq!(contents, b"abc");
+    assert_eq!(
+        digest[..4],
+        [0xba, 0x78, 0x16, 0xbf],
+        "SHA-256 of \"abc\" starts with ba7816bf"
+    );
+}
+
+#[cfg(feature = "disk-space")]
+#[tokio::test]
+async fn checked_download_to_path_writes_file() {
+    let base_url = common::serve_api(|_| Response::json(r#""dltoken""#)).await;
+    let api = common::client(&base_url).await;
+
+    let dir = common::temp_dir("download-to-path-checked");
+    let path = dir.join("file.zip");
+
+    let file = "file.zip".to_string();
+    api.download_to_path_checked(DownloadCategory::User { file: &file }, &path)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), br#""dltoken""#);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn download_href_stays_on_base_url() {
+    let base_url = common::serve_api(|req| match req.headers.get("authorization") {
+        Some(auth) if auth == "Bearer token" && req.path == "/files/file.zip" => {
+            Response::new(200).body("content")
+        }
+        _ => Response::new(401),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut relative = Vec::new();
+    api.download_href("/files/file.zip", &mut relative)
+        .await
+        .unwrap();
+    assert_eq!(relative, b"content");
+
+    let mut absolute = Vec::new();
+    api.download_href(&format!("{}/files/file.zip", base_url), &mut absolute)
+        .await
+        .unwrap();
+    assert_eq!(absolute, b"content");
+
+    let mut elsewhere = Vec::new();
+    assert!(api
+        .download_href("http://example.com/files/file.zip", &mut elsewhere)
+        .await
+        .is_err());
+    assert!(elsewhere.is_empty());
+}
+
+#[tokio::test]
+async fn download_user_files_matching_filters_by_glob() {
+    let base_url = common::serve_api(|req| match req.path.as_str() {
+        "/api/file/GetUserFiles" => Response::json(
+            r#"[
+                { "isFolder": false, "name": "nvdb_gavle_1.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": false, "name": "nvdb_gavle_2.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00" },
+                { "isFolder": false, "name": "nvdb_uppsala.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00" }
+            ]"#,
+        ),
+        _ => Response::json(r#""dltoken""#),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let dir = common::temp_dir("download-matching");
+
+    let results = api
+        .download_user_files_matching("nvdb_gavle_*.zip", &dir, 2)
+        .await
+        .unwrap();
+
+    let mut names: Vec<_> = results
+        .iter()
+        .map(|(file, result)| {
+            assert!(result.is_ok());
+            file.name.as_str()
+        })
+        .collect();
+    names.sort();
+    assert_eq!(names, ["nvdb_gavle_1.zip", "nvdb_gavle_2.zip"]);
+    assert!(dir.join("nvdb_gavle_1.zip").exists());
+    assert!(!dir.join("nvdb_uppsala.zip").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn matching_user_files_does_not_backtrack_exponentially() {
+    let names = [
+        format!("{}.zip", "a".repeat(200)),
+        format!("{}b.zip", "a".repeat(200)),
+        "ab.zip".to_string(),
+    ];
+    let listing = serde_json::to_string(&names.map(|name| {
+        serde_json::json!({ "isFolder": false, "name": name, "size": "1 MB", "dateTime": "2023-11-20T10:15:00" })
+    }))
+    .unwrap();
+    let base_url = common::serve_api(move |req| match req.path.as_str() {
+        "/api/file/GetUserFiles" => Response::json(&listing),
+        _ => Response::json(r#""dltoken""#),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let dir = common::temp_dir("download-matching-pathological");
+
+    let started = Instant::now();
+    let results = api
+        .download_user_files_matching("*a*a*a*a*a*a*a*b?zip", &dir, 2)
+        .await
+        .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let names: Vec<_> = results.iter().map(|(file, _)| file.name.clone()).collect();
+    assert_eq!(names, [format!("{}b.zip", "a".repeat(200))]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn slow_download_stream_holds_back_transfer() {
+    const LEN: usize = 256 << 20;
+
+    let written = Arc::new(AtomicUsize::new(0));
+    let base_url = common::serve_streaming(LEN, written.clone()).await;
+    let api = common::client(&base_url).await;
+
+    let stream = api
+        .download_stream(DownloadToken::User("dltoken".into()))
+        .await
+        .unwrap();
+    futures::pin_mut!(stream);
+    let mut received = stream.try_next().await.unwrap().unwrap().len();
+
+    // Without polling, the server can only get as far as the socket buffers allow.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(written.load(Ordering::SeqCst) < LEN / 4);
+
+    while let Some(chunk) = stream.try_next().await.unwrap() {
+        received += chunk.len();
+    }
+    assert_eq!(received, LEN);
+}
+
+#[tokio::test]
+async fn buffered_download_stream_reads_ahead() {
+    const LEN: usize = 4 << 20;
+
+    let written = Arc::new(AtomicUsize::new(0));
+    let base_url = common::serve_streaming(LEN, written.clone()).await;
+    let api = common::client(&base_url).await;
+
+    let stream = api
+        .download_stream_buffered(DownloadToken::User("dltoken".into()), 4096)
+        .await
+        .unwrap();
+    futures::pin_mut!(stream);
+    let mut received = stream.try_next().await.unwrap().unwrap().len();
+
+    // Unlike download_stream, the transfer carries on into the buffer without polling.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(written.load(Ordering::SeqCst), LEN);
+
+    while let Some(chunk) = stream.try_next().await.unwrap() {
+        received += chunk.len();
+    }
+    assert_eq!(received, LEN);
+}
+
+#[tokio::test]
+async fn expired_download_token_is_minted_again() {
+    let minted = Arc::new(AtomicUsize::new(0));
+    let counter = minted.clone();
+    let base_url = common::serve_api(move |req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = counter.fetch_add(1, Ordering::SeqCst);
+            Response::json(&format!(r#""token{}""#, token))
+        } else if req.path.ends_with("token=token0") {
+            Response::new(401).body("token expired")
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let file = "file.zip".to_string();
+    let mut content = Vec::new();
+    api.download_file(DownloadCategory::User { file: &file }, &mut content)
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"content");
+    assert_eq!(minted.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn failed_download_is_retried_with_a_fresh_token() {
+    let minted = Arc::new(AtomicUsize::new(0));
+    let counter = minted.clone();
+    let base_url = common::serve_api(move |req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = counter.fetch_add(1, Ordering::SeqCst);
+            Response::json(&format!(r#""token{}""#, token))
+        } else if req.path.ends_with("token=token0") {
+            Response::new(503).body("try again")
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_retries(1)
+        .backoff(Duration::from_millis(1))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let file = "file.zip".to_string();
+    let mut content = Vec::new();
+    api.download_file(DownloadCategory::User { file: &file }, &mut content)
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"content");
+    assert_eq!(minted.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn download_reuses_token_request_connection() {
+    let (base_url, connections) = common::serve_keep_alive(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let file = "file.zip".to_string();
+    let mut content = Vec::new();
+    api.download_file(DownloadCategory::User { file: &file }, &mut content)
+        .await
+        .unwrap();
+
+    assert_eq!(content, b"content");
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn download_all_formats_maps_format_to_path() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/DataPackage/GetDataPackageFiles/") {
+            Response::json(
+                r#"[
+                    { "isFolder": false, "name": "Gävleborgs_län_GeoPackage.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                    { "isFolder": false, "name": "Gävleborgs_län_Shape.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] },
+                    { "isFolder": false, "name": "Uppsala_län_Shape.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00+01:00", "links": [] }
+                ]"#,
+            )
+        } else {
+            Response::json(r#""dltoken""#)
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let dir = common::temp_dir("download-all-formats");
+
+    let paths = api
+        .download_all_formats(&1, "Gävleborgs_län_", &dir)
+        .await
+        .unwrap();
+
+    assert_eq!(paths.len(), 2);
+    assert_eq!(
+        paths["GeoPackage"],
+        dir.join("Gävleborgs_län_GeoPackage.zip")
+    );
+    assert!(paths["Shape"].exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn download_checkpoints_after_flushing() {
+    let base_url = common::serve_api(|_| Response::new(200).body(vec![0u8; 4096])).await;
+    let api = common::client(&base_url).await;
+
+    let mut checkpoints = Vec::new();
+    api.download_with_checkpoints(
+        DownloadToken::User("dltoken".into()),
+        &mut io::sink(),
+        CheckpointInterval::Bytes(1024),
+        |bytes| {
+            checkpoints.push(bytes);
+            Ok(())
+        },
+    )
+    .await
+    .unwrap();
+
+    assert!(checkpoints.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(checkpoints.last(), Some(&4096));
+
+    let aborted = api
+        .download_with_checkpoints(
+            DownloadToken::User("dltoken".into()),
+            &mut io::sink(),
+            CheckpointInterval::Bytes(1024),
+            |_| Err(LastkajenError::LastkajenError("checkpoint failed".into())),
+        )
+        .await;
+    assert!(aborted.is_err());
+}
+
+#[tokio::test]
+async fn redirects_can_be_disabled() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetFileStream") {
+            Response::new(302).header("Location", "/cdn/file.zip")
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(&base_url)
+        .redirect_policy(reqwest::redirect::Policy::none())
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let mut content = Vec::new();
+    let result = api
+        .download_with_token(DownloadToken::User("dltoken".into()), &mut content)
+        .await;
+
+    assert!(matches!(result, Err(LastkajenError::ApiError(status, _)) if status == 302));
+
+    let mut followed = Vec::new();
+    common::client(&base_url)
+        .await
+        .download_with_token(DownloadToken::User("dltoken".into()), &mut followed)
+        .await
+        .unwrap();
+    assert_eq!(followed, b"content");
+}
+
+#[tokio::test]
+async fn download_reader_outlives_client() {
+    let base_url = common::serve_api(|_| Response::new(200).body("content")).await;
+    let api = common::client(&base_url).await;
+
+    let mut reader = api
+        .download_reader(DownloadToken::User("dltoken".into()))
+        .await
+        .unwrap();
+    drop(api);
+
+    let mut content = Vec::new();
+    let copied = tokio::spawn(async move {
+        tokio::io::copy(&mut reader, &mut content)
+            .await
+            .map(|_| content)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(copied, b"content");
+}
+
+#[tokio::test]
+async fn download_package_file_saves_under_dir() {
+    let base_url = common::serve_api(|_| Response::json(r#""dltoken""#)).await;
+    let api = common::client(&base_url).await;
+    let dir = common::temp_dir("download-package-file");
+
+    let package: DataPackageFolder = serde_json::from_value(serde_json::json!({
+        "id": 1,
+        "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+        "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+        "name": "Gävleborgs län",
+        "description": "",
+        "published": true,
+    }))
+    .unwrap();
+    let file: DataPackageFile = serde_json::from_value(serde_json::json!({
+        "isFolder": false,
+        "name": "Gävleborgs_län_GeoPackage.zip",
+        "size": "1 MB",
+        "dateTime": "2023-11-20T10:15:00+01:00",
+        "links": [],
+    }))
+    .unwrap();
+
+    let path = api
+        .download_package_file(&package, &file, &dir)
+        .await
+        .unwrap();
+
+    assert_eq!(path, dir.join("Gävleborgs_län_GeoPackage.zip"));
+    assert!(path.exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn verified_download_checks_size_and_sets_modified() {
+    // The mock serves the same 9 byte body for tokens and downloads alike.
+    let base_url = common::serve_api(|_| Response::json(r#""dltoken""#)).await;
+    let api = common::client(&base_url).await;
+    let dir = common::temp_dir("download-package-file-verified");
+
+    let package: DataPackageFolder = serde_json::from_value(serde_json::json!({
+        "id": 1,
+        "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+        "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+        "name": "Gävleborgs län",
+        "description": "",
+        "published": true,
+    }))
+    .unwrap();
+    let mut file: DataPackageFile = serde_json::from_value(serde_json::json!({
+        "isFolder": false,
+        "name": "Gävleborgs_län_GeoPackage.zip",
+        "size": "9 B",
+        "dateTime": "2023-11-20T10:15:00+01:00",
+        "links": [],
+    }))
+    .unwrap();
+
+    #[cfg(feature = "time")]
+    {
+        let verification = api
+            .download_package_file_verified(&package, &file, &dir, true)
+            .await
+            .unwrap();
+
+        assert_eq!(verification.bytes, 9);
+        assert!(verification.is_ok());
+        let modified = std::fs::metadata(&verification.path)
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(modified, std::time::SystemTime::from(file.date_time));
+    }
+    #[cfg(not(feature = "time"))]
+    {
+        let result = api
+            .download_package_file_verified(&package, &file, &dir, true)
+            .await;
+        assert!(matches!(result, Err(LastkajenError::LastkajenError(_))));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    let verification = api
+        .download_package_file_verified(&package, &file, &dir, false)
+        .await
+        .unwrap();
+    assert_eq!(verification.bytes, 9);
+    assert!(verification.is_ok());
+
+    // A mismatched download is reported, but already in place.
+    file.size = FileSize::parse("1 MB");
+    let verification = api
+        .download_package_file_verified(&package, &file, &dir, false)
+        .await
+        .unwrap();
+    assert_eq!(verification.size_matches, Some(false));
+    assert!(!verification.is_ok());
+    assert_eq!(std::fs::read(&verification.path).unwrap().len(), 9);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn downloads_are_streamed_into_a_tar_archive() {
+    use std::io::Read;
+
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = if req.path.contains("short") { "a" } else { "b" };
+            Response::json(&format!(r#""{}""#, token))
+        } else if req.path.ends_with("token=a") {
+            Response::new(200).body(vec![b'a'; 600])
+        } else {
+            Response::new(200).body("bbbbbbbbbb")
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    // Long enough to need a GNU long name entry.
+    let long = format!("{}.zip", "long".repeat(30));
+    let short = "short.zip".to_string();
+    let categories = [
+        DownloadCategory::User { file: &short },
+        DownloadCategory::User { file: &long },
+    ];
+    let mut archive = Vec::new();
+    api.download_to_archive(&categories, &mut archive)
+        .await
+        .unwrap();
+
+    let mut archive = tar::Archive::new(archive.as_slice());
+    let entries: Vec<(String, Vec<u8>)> = archive
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content).unwrap();
+            (entry.path().unwrap().display().to_string(), content)
+        })
+        .collect();
+
+    assert_eq!(
+        entries,
+        [(short, vec![b'a'; 600]), (long, b"bbbbbbbbbb".to_vec())]
+    );
+}
+
+#[cfg(feature = "archive")]
+#[tokio::test]
+async fn failed_archive_downloads_are_left_unfinished() {
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            let token = if req.path.contains("broken") {
+                "b"
+            } else {
+                "a"
+            };
+            Response::json(&format!(r#""{}""#, token))
+        } else if req.path.ends_with("token=a") {
+            Response::new(200).body(vec![b'a'; 600])
+        } else {
+            Response::new(500).body("broken")
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let fine = "fine.zip".to_string();
+    let broken = "broken.zip".to_string();
+    let categories = [
+        DownloadCategory::User { file: &fine },
+        DownloadCategory::User { file: &broken },
+    ];
+
+    let mut archive = Vec::new();
+    let result = api.download_to_archive(&categories, &mut archive).await;
+    assert!(matches!(result, Err(LastkajenError::ApiError(..))));
+    // Just the first entry's header and padded body, without the two zero blocks ending an
+    // archive.
+    assert_eq!(archive.len(), 512 + 1024);
+
+    let dir = common::temp_dir("failed-archive");
+    let path = dir.join("files.tar");
+    let result = api.download_to_archive_path(&categories, &path).await;
+    assert!(matches!(result, Err(LastkajenError::ApiError(..))));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+    api.download_to_archive_path(&categories[..1], &path)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tee_writes_every_sink_and_names_the_failing_one() {
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let base_url = common::serve_api(|req| {
+        if req.path.starts_with("/api/file/GetUserFileDownloadToken") {
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    let file = "file.zip".to_string();
+
+    let (mut first, mut second) = (Vec::new(), Vec::new());
+    api.download_tee(
+        DownloadCategory::User { file: &file },
+        &mut [&mut first, &mut second],
+    )
+    .await
+    .unwrap();
+    assert_eq!(first, b"content");
+    assert_eq!(second, b"content");
+
+    let mut saved = Vec::new();
+    let result = api
+        .download_tee(
+            DownloadCategory::User { file: &file },
+            &mut [&mut saved, &mut Full],
+        )
+        .await;
+    assert!(matches!(result, Err(LastkajenError::SinkFailed(1, _))));
+}
+
+#[tokio::test]
+async fn allowlist_refuses_other_packages_before_minting() {
+    let minted = Arc::new(AtomicUsize::new(0));
+    let counter = minted.clone();
+    let listings = Arc::new(AtomicUsize::new(0));
+    let listed = listings.clone();
+    let base_url = common::serve_api(move |req| {
+        if req
+            .path
+            .starts_with("/api/DataPackage/GetPublishedDataPackages")
+        {
+            listed.fetch_add(1, Ordering::SeqCst);
+            let packages = [1, 2].map(|id| {
+                serde_json::json!({
+                    "id": id,
+                    "targetFolder": { "id": 1, "name": "Länsfiler", "path": "Länsfiler" },
+                    "sourceFolder": format!("Datapaket\\Länsfiler NVDB-data\\Län {}", id),
+                    "name": "",
+                    "description": "",
+                    "published": true,
+                })
+            });
+            Response::json(&serde_json::to_string(&packages).unwrap())
+        } else if req.path.contains("DownloadToken") {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let by_id = Lastkajen::builder()
+        .base_url(&base_url)
+        .allowed_package_ids([1].into())
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    let by_folder = Lastkajen::builder()
+        .base_url(&base_url)
+        .allowed_source_folder_prefixes(vec!["Datapaket\\Länsfiler NVDB-data\\Län 2".into()])
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let file = "file.zip".to_string();
+    async fn download(api: &Lastkajen, id: usize) -> Result<(), LastkajenError> {
+        let file = "file.zip".to_string();
+        let category = DownloadCategory::Published {
+            id: &id,
+            file: &file,
+        };
+        api.download_file(category, &mut Vec::new()).await
+    }
+    let refused =
+        |result: Result<_, LastkajenError>| matches!(result, Err(LastkajenError::NotAllowed(_)));
+
+    assert!(download(&by_id, 1).await.is_ok());
+    assert!(refused(download(&by_id, 2).await));
+    assert!(refused(download(&by_folder, 1).await));
+    assert!(download(&by_folder, 2).await.is_ok());
+    assert!(download(&by_folder, 2).await.is_ok());
+    // The listing is only fetched again for packages it didn't have.
+    assert_eq!(listings.load(Ordering::SeqCst), 1);
+    assert!(refused(download(&by_folder, 3).await));
+    assert_eq!(listings.load(Ordering::SeqCst), 2);
+
+    let user = DownloadCategory::User { file: &file };
+    assert!(refused(by_id.get_download_token(user).await.map(drop)));
+    let href = "/api/file/GetFileStream?token=dltoken";
+    assert!(refused(by_id.download_href(href, &mut Vec::new()).await));
+
+    assert_eq!(minted.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn allowlisted_prefixes_match_whole_folder_names() {
+    let base_url = common::serve_api(|req| {
+        if req
+            .path
+            .starts_with("/api/DataPackage/GetPublishedDataPackages")
+        {
+            let folders = [
+                "Datapaket\\Trafik",
+                "Datapaket\\Trafik\\Flöden",
+                "Datapaket\\Trafikflöden",
+            ];
+            let packages: Vec<_> = folders
+                .iter()
+                .enumerate()
+                .map(|(id, folder)| {
+                    serde_json::json!({
+                        "id": id,
+                        "targetFolder": { "id": 1, "name": "Trafik", "path": "Trafik" },
+                        "sourceFolder": folder,
+                        "name": "",
+                        "description": "",
+                        "published": true,
+                    })
+                })
+                .collect();
+            Response::json(&serde_json::to_string(&packages).unwrap())
+        } else if req.path.contains("DownloadToken") {
+            Response::json(r#""dltoken""#)
+        } else {
+            Response::new(200).body("content")
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(&base_url)
+        .allowed_source_folder_prefixes(vec!["Datapaket\\Trafik".into()])
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let file = "file.zip".to_string();
+    for (id, allowed) in [(0, true), (1, true), (2, false)] {
+        let category = DownloadCategory::Published {
+            id: &id,
+            file: &file,
+        };
+        let result = api.download_file(category, &mut Vec::new()).await;
+        if allowed {
+            result.unwrap();
+        } else {
+            assert!(matches!(result, Err(LastkajenError::NotAllowed(_))));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use lastkajen::util::DetectedFormat;
+use lastkajen::LastkajenError;
+use reqwest::StatusCode;
+
+/// A connection error, from a port nothing listens on.
+async fn connect_error() -> LastkajenError {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+
+    reqwest::get(url).await.unwrap_err().into()
+}
+
+#[tokio::test]
+async fn errors_are_classified() {
+    let api =
+        |status: u16| LastkajenError::ApiError(StatusCode::from_u16(status).unwrap(), "".into());
+    let cases = [
+        // (error, retryable, transient)
+        (connect_error().await, true, true),
+        (api(500), true, true),
+        (api(503), true, true),
+        // A bare rate limit; a 429 mentioning the quota is QuotaExceeded.
+        (api(429), true, true),
+        (
+            LastkajenError::StatusError(StatusCode::BAD_GATEWAY),
+            true,
+            true,
+        ),
+        (api(400), false, false),
+        (api(401), false, false),
+        (api(404), false, false),
+        (
+            LastkajenError::StatusError(StatusCode::FORBIDDEN),
+            false,
+            false,
+        ),
+        (
+            serde_json::from_str::<u8>("x").unwrap_err().into(),
+            false,
+            false,
+        ),
+        (std::io::Error::other("disk full").into(), false, false),
+        (
+            LastkajenError::SinkFailed(1, std::io::Error::other("disk full")),
+            false,
+            false,
+        ),
+        (LastkajenError::NotFound("package".into()), false, false),
+        (LastkajenError::Ambiguous("package".into()), false, false),
+        (LastkajenError::EmptyResponse, false, false),
+        (LastkajenError::NotAllowed("package 2".into()), false, false),
+        (LastkajenError::InsufficientSpace(2, 1), false, false),
+        (LastkajenError::Maintenance, false, true),
+        (LastkajenError::ResponseTooLarge(1024), false, false),
+        (LastkajenError::DownloadTooLarge(1024), false, false),
+        (LastkajenError::RangeNotSupported, false, false),
+        (
+            LastkajenError::FormatMismatch(DetectedFormat::Zip, DetectedFormat::Html),
+            false,
+            false,
+        ),
+        (
+            LastkajenError::QuotaExceeded {
+                retry_after: Some(Duration::from_secs(60)),
+            },
+            false,
+            true,
+        ),
+        (
+            LastkajenError::RefreshFailed(Arc::new(api(503))),
+            false,
+            true,
+        ),
+        (
+            LastkajenError::RefreshFailed(Arc::new(api(401))),
+            false,
+            false,
+        ),
+        (LastkajenError::LastkajenError("other".into()), false, false),
+    ];
+
+    for (err, retryable, transient) in cases {
+        assert_eq!(err.is_retryable(), retryable, "{}", err);
+        assert_eq!(err.is_transient(), transient, "{}", err);
+    }
+}
+
+#[test]
+fn io_errors_keep_their_kind() {
+    let not_found = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+    let err = std::io::Error::from(LastkajenError::IoError(not_found));
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(err.to_string(), "no such file");
+
+    let err = std::io::Error::from(LastkajenError::NotFound("package".into()));
+    assert_eq!(err.kind(), std::io::ErrorKind::Other);
+    assert!(err
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<LastkajenError>())
+        .is_some_and(|inner| matches!(inner, LastkajenError::NotFound(_))));
+}
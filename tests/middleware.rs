@@ -0,0 +1,34 @@
+#![cfg(feature = "middleware")]
+
+mod common;
+
+use common::Response;
+use lastkajen::Lastkajen;
+use reqwest::header::{HeaderMap, HeaderValue};
+
+#[tokio::test]
+async fn requests_go_through_middleware_client() {
+    let base_url = common::serve(|req| match req.headers.get("x-through") {
+        Some(value) if value == "middleware" => match req.path.as_str() {
+            common::LOGIN_PATH => Response::json(common::TOKEN),
+            _ => Response::json("[]"),
+        },
+        _ => Response::new(400),
+    })
+    .await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-through", HeaderValue::from_static("middleware"));
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .build()
+        .unwrap();
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .middleware(reqwest_middleware::ClientBuilder::new(client).build())
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
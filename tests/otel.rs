@@ -0,0 +1,90 @@
+#![cfg(feature = "otel")]
+
+mod common;
+
+use common::Response;
+use lastkajen::types::DownloadToken;
+use lastkajen::Lastkajen;
+use opentelemetry::trace::Status;
+use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+use opentelemetry_sdk::trace::TracerProvider;
+
+/// Download token that must never show up in a span.
+const SECRET: &str = "secret-download-token";
+
+#[tokio::test]
+async fn tokens_stay_out_of_spans() {
+    let exporter = InMemorySpanExporter::default();
+    opentelemetry::global::set_tracer_provider(
+        TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build(),
+    );
+
+    let base_url = common::serve_api(|_| Response::new(500).body(SECRET)).await;
+    let token = || DownloadToken::User(SECRET.into());
+
+    // An error response, which is recorded as such.
+    let api = common::client(&base_url).await;
+    assert!(api
+        .download_with_token(token(), &mut Vec::new())
+        .await
+        .is_err());
+
+    // A failed connection, whose reqwest error mentions the url.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_url = format!("http://{}", closed.local_addr().unwrap());
+    drop(closed);
+    let unreachable = Lastkajen::builder()
+        .base_url(closed_url)
+        .build_with_api_key(SECRET.into())
+        .unwrap();
+    assert!(unreachable
+        .download_with_token(token(), &mut Vec::new())
+        .await
+        .is_err());
+
+    // A failing middleware, whose error message is its own.
+    #[cfg(feature = "middleware")]
+    {
+        use futures::future::BoxFuture;
+        use reqwest_middleware::{Next, Result};
+        use task_local_extensions::Extensions;
+
+        fn refuse<'a>(
+            req: reqwest::Request,
+            _: &'a mut Extensions,
+            _: Next<'a>,
+        ) -> BoxFuture<'a, Result<reqwest::Response>> {
+            Box::pin(async move {
+                let message = format!("refused {}", req.url());
+                Err(reqwest_middleware::Error::middleware(
+                    std::io::Error::other(message),
+                ))
+            })
+        }
+
+        let middleware = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(refuse)
+            .build();
+        let api = Lastkajen::builder()
+            .base_url(&base_url)
+            .middleware(middleware)
+            .build_with_api_key(SECRET.into())
+            .unwrap();
+        assert!(api
+            .download_with_token(token(), &mut Vec::new())
+            .await
+            .is_err());
+    }
+
+    let spans = exporter.get_finished_spans().unwrap();
+    for span in &spans {
+        assert!(!format!("{:?}", span).contains(SECRET), "{:?}", span);
+    }
+    let failed = spans
+        .iter()
+        .filter(|span| matches!(span.status, Status::Error { .. }))
+        .count();
+    assert_eq!(failed, if cfg!(feature = "middleware") { 3 } else { 2 });
+}
@@ -0,0 +1,579 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::Response;
+use lastkajen::types::{DownloadCategory, DownloadToken};
+use lastkajen::{Lastkajen, LastkajenError};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn no_content_is_an_empty_list() {
+    let base_url = common::serve_api(|_| Response::new(204)).await;
+    let api = common::client(&base_url).await;
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn empty_body_is_an_empty_list() {
+    let base_url = common::serve_api(|_| Response::new(200)).await;
+    let api = common::client(&base_url).await;
+
+    assert!(api.get_published_packages().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn empty_body_is_a_typed_error_for_single_values() {
+    let base_url = common::serve_api(|_| Response::new(200)).await;
+    let api = common::client(&base_url).await;
+
+    let file = "file.zip".to_string();
+    let result = api
+        .get_download_token(DownloadCategory::User { file: &file })
+        .await;
+
+    assert!(matches!(result, Err(LastkajenError::EmptyResponse)));
+}
+
+#[tokio::test]
+async fn json_body_is_parsed() {
+    let base_url = common::serve_api(|_| Response::json(r#""dltoken""#)).await;
+    let api = common::client(&base_url).await;
+
+    let file = "file.zip".to_string();
+    let token = api
+        .get_download_token(DownloadCategory::User { file: &file })
+        .await
+        .unwrap();
+
+    assert!(matches!(token, DownloadToken::User(token) if token == "dltoken"));
+}
+
+#[cfg(feature = "timing")]
+#[tokio::test]
+async fn timing_hook_is_called_once_per_request() {
+    let base_url = common::serve_api(|req| {
+        if req
+            .path
+            .starts_with("/api/DataPackage/GetPublishedDataPackages")
+        {
+            Response::json("[]")
+        } else {
+            Response::new(200).body("[]")
+        }
+    })
+    .await;
+    let timings = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let collected = timings.clone();
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .on_timing(move |timing| collected.lock().unwrap().push(timing.clone()))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    api.get_published_packages().await.unwrap();
+    api.get_user_files_raw(HeaderMap::new()).await.unwrap();
+
+    let timings = timings.lock().unwrap();
+    let paths: Vec<_> = timings.iter().map(|timing| timing.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        [
+            "/api/DataPackage/GetPublishedDataPackages",
+            "/api/file/GetUserFiles"
+        ]
+    );
+    assert!(timings.iter().all(|timing| timing.headers <= timing.total));
+    assert_eq!(timings[1].headers, timings[1].total);
+}
+
+#[tokio::test]
+async fn api_key_is_sent_as_header() {
+    let base_url = common::serve(|req| match req.headers.get("ocp-apim-subscription-key") {
+        Some(key) if key == "key" && !req.headers.contains_key("authorization") => {
+            Response::json("[]")
+        }
+        _ => Response::new(401),
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .build_with_api_key("key".into())
+        .unwrap();
+
+    assert!(api.token().is_none());
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn api_key_is_not_sent_on_to_other_hosts() {
+    let reached = Arc::new(AtomicUsize::new(0));
+    let counter = reached.clone();
+    let other_url = common::serve(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        Response::json("[]")
+    })
+    .await;
+    let base_url = common::serve(move |req| {
+        if req.path == "/moved" {
+            match req.headers.get("ocp-apim-subscription-key") {
+                Some(key) if key == "key" => Response::json("[]"),
+                _ => Response::new(401),
+            }
+        } else if req
+            .path
+            .starts_with("/api/DataPackage/GetPublishedDataPackages")
+        {
+            Response::new(302).header("Location", "/moved")
+        } else {
+            Response::new(302).header("Location", &format!("{}/elsewhere", other_url))
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .build_with_api_key("key".into())
+        .unwrap();
+
+    assert!(api.get_published_packages().await.unwrap().is_empty());
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ApiError(StatusCode::FOUND, _))
+    ));
+    let result = api
+        .download_with_token(DownloadToken::User("dltoken".into()), &mut Vec::new())
+        .await;
+    assert!(matches!(
+        result,
+        Err(LastkajenError::ApiError(StatusCode::FOUND, _))
+    ));
+    assert_eq!(reached.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn raw_methods_send_extra_headers() {
+    let base_url = common::serve_api(|req| match req.headers.get("x-feature") {
+        Some(value) if value == "on" => Response::json("[]"),
+        _ => Response::new(400),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-feature", HeaderValue::from_static("on"));
+    let res = api.get_user_files_raw(headers).await.unwrap();
+
+    assert_eq!(res.text().await.unwrap(), "[]");
+}
+
+#[tokio::test]
+async fn ping_reports_rejected_sessions() {
+    let base_url = common::serve_api(|req| match req.headers.get("authorization") {
+        Some(auth) if auth == "Bearer token" => Response::json("[]"),
+        _ => Response::new(401),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+    assert!(api.ping().await.is_ok());
+
+    let base_url = common::serve_api(|_| Response::new(401).body("Unauthorized")).await;
+    let api = common::client(&base_url).await;
+    assert!(matches!(
+        api.ping().await,
+        Err(LastkajenError::ApiError(status, _)) if status == 401
+    ));
+}
+
+#[tokio::test]
+async fn default_headers_are_sent_with_every_request() {
+    let base_url = common::serve(|req| match req.headers.get("accept-language") {
+        Some(language) if language == "sv-SE" => match req.path.as_str() {
+            common::LOGIN_PATH => Response::json(common::TOKEN),
+            _ => Response::json("[]"),
+        },
+        _ => Response::new(400),
+    })
+    .await;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("accept-language", HeaderValue::from_static("sv-SE"));
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .default_headers(headers)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn lenient_listing_skips_malformed_packages() {
+    let base_url = common::serve_api(|_| {
+        Response::json(
+            r#"[
+                {
+                    "id": 1,
+                    "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+                    "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+                    "name": "Gävleborgs län",
+                    "description": "",
+                    "published": true
+                },
+                { "id": "not a package" }
+            ]"#,
+        )
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    assert!(api.get_published_packages().await.is_err());
+
+    let (packages, issues) = api.get_published_packages_lenient().await.unwrap();
+    assert_eq!(packages.len(), 1);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].index, 1);
+}
+
+#[tokio::test]
+async fn oversized_json_is_rejected() {
+    let base_url = common::serve_api(|_| Response::json(&format!("[{}]", " ".repeat(64)))).await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_json_bytes(16)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ResponseTooLarge(16))
+    ));
+}
+
+#[tokio::test]
+async fn request_middleware_sees_every_request() {
+    let base_url = common::serve(|req| match req.headers.get("x-signature") {
+        Some(signature) if signature == "signed" => match req.path.as_str() {
+            common::LOGIN_PATH => Response::json(common::TOKEN),
+            _ => Response::json("[]"),
+        },
+        _ => Response::new(400),
+    })
+    .await;
+
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .request_middleware(|mut request| {
+            request
+                .headers_mut()
+                .insert("x-signature", HeaderValue::from_static("signed"));
+            request
+        })
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn external_account_flag_comes_from_token() {
+    let base_url = common::serve(|_| {
+        Response::json(r#"{"access_token":"token","expires_in":3600,"is_external":true}"#)
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    assert!(api.is_external_account());
+}
+
+#[tokio::test]
+async fn maintenance_page_is_a_typed_error() {
+    let base_url = common::serve_api(|_| {
+        Response::new(200)
+            .header("Content-Type", "text/html")
+            .body("<html><body><h1>Lastkajen är stängd för underhåll</h1></body></html>")
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::Maintenance)
+    ));
+}
+
+#[tokio::test]
+async fn quota_exceeded_carries_retry_after() {
+    let base_url = common::serve_api(|_| {
+        Response::new(429)
+            .header("Retry-After", "3600")
+            .body("Daglig nedladdningskvot uppnådd")
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::QuotaExceeded {
+            retry_after: Some(retry_after)
+        }) if retry_after.as_secs() == 3600
+    ));
+}
+
+#[tokio::test]
+async fn bare_rate_limits_are_not_quota_errors() {
+    let base_url = common::serve_api(|_| {
+        Response::new(429)
+            .header("Retry-After", "1")
+            .body("Too many requests")
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let err = api.get_user_files().await.unwrap_err();
+    assert!(matches!(
+        err,
+        LastkajenError::ApiError(StatusCode::TOO_MANY_REQUESTS, _)
+    ));
+    assert!(err.is_retryable());
+}
+
+#[tokio::test]
+async fn rate_limited_requests_are_retried_after_the_wait() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let base_url = common::serve_api(move |_| match counter.fetch_add(1, Ordering::SeqCst) {
+        0 => Response::new(429)
+            .header("Retry-After", "1")
+            .body("Too many requests"),
+        _ => Response::json("[]"),
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_retries(1)
+        .backoff(Duration::from_millis(10))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let started = Instant::now();
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn quota_exceeded_accepts_http_dates() {
+    let base_url = common::serve_api(|_| {
+        Response::new(403)
+            .header("Retry-After", "Wed, 21 Oct 2015 07:28:00 GMT")
+            .body("Daily download quota exceeded")
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    // A date in the past means there's no need to wait.
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::QuotaExceeded {
+            retry_after: Some(retry_after)
+        }) if retry_after.is_zero()
+    ));
+}
+
+fn gzip(body: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[tokio::test]
+async fn gzipped_json_is_decompressed() {
+    let base_url = common::serve_api(|req| match req.headers.get("accept-encoding") {
+        Some(encoding) if encoding == "gzip" => Response::json("")
+            .header("Content-Encoding", "gzip")
+            .body(gzip(br#"[{ "isFolder": false, "name": "a.zip", "size": "1 MB", "dateTime": "2023-11-20T10:15:00" }]"#)),
+        _ => Response::new(406),
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let files = api.get_user_files().await.unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name, "a.zip");
+}
+
+#[tokio::test]
+async fn decompressed_json_is_still_capped() {
+    let body = gzip(format!("[{}]", " ".repeat(4096)).as_bytes());
+    let base_url = common::serve_api(move |_| {
+        Response::json("")
+            .header("Content-Encoding", "gzip")
+            .body(body.clone())
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .max_json_bytes(1024)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ResponseTooLarge(1024))
+    ));
+}
+
+#[tokio::test]
+async fn json_decompression_can_be_turned_off() {
+    let base_url = common::serve_api(|req| match req.headers.get("accept-encoding") {
+        None => Response::json("[]"),
+        Some(_) => Response::new(400),
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .decompress_json_responses(false)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn downloads_are_never_decompressed() {
+    let zip = gzip(b"PK\x03\x04 not really a zip");
+    let served = zip.clone();
+    let base_url = common::serve_api(move |req| {
+        if req.path.starts_with("/api/file/GetFileStream") {
+            assert_eq!(
+                req.headers.get("accept-encoding").map(String::as_str),
+                Some("identity")
+            );
+            // A misbehaving server labelling the file's own compression as transfer encoding.
+            Response::new(200)
+                .header("Content-Encoding", "gzip")
+                .body(served.clone())
+        } else {
+            Response::json(r#""token""#)
+        }
+    })
+    .await;
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .default_headers(HeaderMap::from_iter([(
+            reqwest::header::ACCEPT_ENCODING,
+            HeaderValue::from_static("gzip"),
+        )]))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    let mut written = Vec::new();
+    api.download_with_token(DownloadToken::User("token".into()), &mut written)
+        .await
+        .unwrap();
+    assert_eq!(written, zip);
+}
+
+#[tokio::test]
+async fn each_endpoint_allows_its_own_statuses() {
+    let base_url = common::serve(|req| match req.path.as_str() {
+        common::LOGIN_PATH => Response::new(204),
+        _ => Response::new(206).body("partial"),
+    })
+    .await;
+    let login = Lastkajen::builder()
+        .base_url(&base_url)
+        .build("user".into(), "password".into())
+        .await;
+    assert!(matches!(
+        login,
+        Err(LastkajenError::ApiError(status, _)) if status == StatusCode::NO_CONTENT
+    ));
+
+    let base_url = common::serve_api(|_| Response::new(206).body("partial")).await;
+    let api = common::client(&base_url).await;
+    let token = || DownloadToken::User("dltoken".into());
+
+    // A partial answer is only passed on by the raw download, which may have asked for it.
+    let res = api.download_raw(token(), HeaderMap::new()).await.unwrap();
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert!(matches!(
+        api.download_with_token(token(), &mut Vec::new()).await,
+        Err(LastkajenError::ApiError(status, body))
+            if status == StatusCode::PARTIAL_CONTENT && body == "partial"
+    ));
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ApiError(status, _)) if status == StatusCode::PARTIAL_CONTENT
+    ));
+}
+
+#[tokio::test]
+async fn timeouts_cover_whole_requests_or_connects_only() {
+    let base_url = common::serve_stalling(1, Duration::from_millis(300)).await;
+    let token = || DownloadToken::User("dltoken".into());
+
+    let api = Lastkajen::builder()
+        .base_url(&base_url)
+        .timeout(Duration::from_millis(100))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    assert!(matches!(
+        api.download_with_token(token(), &mut Vec::new()).await,
+        Err(LastkajenError::ReqwestError(err)) if err.is_timeout()
+    ));
+
+    // A connect timeout doesn't limit slow transfers.
+    let api = Lastkajen::builder()
+        .base_url(&base_url)
+        .connect_timeout(Duration::from_millis(100))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    let mut body = Vec::new();
+    api.download_with_token(token(), &mut body).await.unwrap();
+    assert_eq!(body, [0]);
+
+    // A listener that never accepts, with its backlog filled up so further connects hang.
+    let socket = tokio::net::TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(1).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut backlog = Vec::new();
+    while let Ok(stream) = tokio::time::timeout(
+        Duration::from_millis(100),
+        tokio::net::TcpStream::connect(addr),
+    )
+    .await
+    {
+        backlog.push(stream.unwrap());
+    }
+
+    let api = Lastkajen::builder()
+        .base_url(format!("http://{}", addr))
+        .connect_timeout(Duration::from_millis(100))
+        .build_with_api_key("key".into())
+        .unwrap();
+    let started = Instant::now();
+    assert!(matches!(
+        api.ping().await,
+        Err(LastkajenError::ReqwestError(err)) if err.is_connect() && err.is_timeout()
+    ));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
@@ -0,0 +1,134 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::Response;
+use lastkajen::{Lastkajen, LastkajenBuilder, LastkajenError};
+use reqwest::StatusCode;
+
+/// Serve `failures` responses with `status` before succeeding, counting requests other than
+/// logins.
+async fn serve_failing(status: u16, failures: usize, requests: Arc<AtomicUsize>) -> String {
+    common::serve_api(move |_| {
+        if requests.fetch_add(1, Ordering::SeqCst) < failures {
+            Response::new(status).body("failed")
+        } else {
+            Response::json("[]")
+        }
+    })
+    .await
+}
+
+fn builder(base_url: String) -> LastkajenBuilder {
+    Lastkajen::builder()
+        .base_url(base_url)
+        .backoff(Duration::from_millis(1))
+}
+
+#[tokio::test]
+async fn failed_requests_are_retried_up_to_max_retries() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let base_url = serve_failing(500, 2, requests.clone()).await;
+    let api = builder(base_url)
+        .max_retries(2)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn retries_give_up_with_the_last_error() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let base_url = serve_failing(503, usize::MAX, requests.clone()).await;
+    let api = builder(base_url)
+        .max_retries(2)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ApiError(StatusCode::SERVICE_UNAVAILABLE, _))
+    ));
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn requests_are_not_retried_by_default() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let base_url = serve_failing(500, 1, requests.clone()).await;
+    let api = builder(base_url)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.is_err());
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn retry_predicate_decides_what_is_retried() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let base_url = serve_failing(404, 1, requests.clone()).await;
+
+    // Not retryable by default.
+    let api = builder(base_url.clone())
+        .max_retries(3)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    assert!(matches!(
+        api.get_user_files().await,
+        Err(LastkajenError::ApiError(StatusCode::NOT_FOUND, _))
+    ));
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+    requests.store(0, Ordering::SeqCst);
+    let api = builder(base_url)
+        .max_retries(3)
+        .retry_if(|err| matches!(err, LastkajenError::ApiError(StatusCode::NOT_FOUND, _)))
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(requests.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn backoff_doubles_between_attempts() {
+    const BACKOFF: Duration = Duration::from_millis(50);
+
+    let attempts = Arc::new(Mutex::new(Vec::new()));
+    let recorded = attempts.clone();
+    let base_url = common::serve_api(move |_| {
+        recorded.lock().unwrap().push(Instant::now());
+        Response::new(500)
+    })
+    .await;
+    let api = builder(base_url)
+        .max_retries(3)
+        .backoff(BACKOFF)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.is_err());
+
+    let attempts = attempts.lock().unwrap();
+    assert_eq!(attempts.len(), 4);
+    for (retry, pair) in attempts.windows(2).enumerate() {
+        let waited = pair[1] - pair[0];
+        let backoff = BACKOFF * 2u32.pow(retry as u32);
+        assert!(
+            waited >= backoff && waited < backoff + Duration::from_millis(200),
+            "retry {} waited {:?}",
+            retry,
+            waited
+        );
+    }
+}
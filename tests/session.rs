@@ -0,0 +1,253 @@
+#![cfg(feature = "time")]
+
+mod common;
+
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::Response;
+use lastkajen::clock::Clock;
+use lastkajen::{Lastkajen, LastkajenError, LoginRequest};
+
+/// Token which is already due for a refresh.
+const EXPIRED_TOKEN: &str = r#"{"access_token":"expired","expires_in":0,"is_external":false}"#;
+
+/// Serve an expired token on the first login and fresh ones after, counting logins.
+async fn serve_expiring(logins: Arc<AtomicUsize>) -> String {
+    common::serve(move |req| match req.path.as_str() {
+        common::LOGIN_PATH => match logins.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::json(EXPIRED_TOKEN),
+            _ => Response::json(common::TOKEN),
+        },
+        _ => match req.headers.get("authorization") {
+            Some(auth) if auth == "Bearer token" => Response::json("[]"),
+            _ => Response::new(401),
+        },
+    })
+    .await
+}
+
+#[test]
+fn obtained_at_round_trips() {
+    use time::format_description::well_known::Iso8601;
+
+    let obtained_at = time::OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789)
+        .unwrap()
+        .to_offset(time::UtcOffset::from_hms(1, 0, 0).unwrap());
+    let json = serde_json::json!({
+        "access_token": "token",
+        "expires_in": 3600,
+        "is_external": false,
+        "obtained_at": obtained_at.format(&Iso8601::DEFAULT).unwrap(),
+    });
+
+    let token: lastkajen::types::Token = serde_json::from_value(json).unwrap();
+    assert_eq!(token.obtained_at, Some(obtained_at));
+    assert_eq!(
+        token.expiry_date_time(),
+        Some(obtained_at + time::Duration::hours(1))
+    );
+}
+
+#[tokio::test]
+async fn concurrent_refreshes_log_in_once() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let api = common::client(&serve_expiring(logins.clone()).await).await;
+
+    let results = futures::future::join_all((0..5).map(|_| api.refresh_if_needed())).await;
+    let refreshed = results
+        .into_iter()
+        .filter(|result| *result.as_ref().unwrap())
+        .count();
+
+    assert_eq!(refreshed, 1);
+    assert_eq!(logins.load(Ordering::SeqCst), 2);
+    assert_eq!(api.token().unwrap().access_token, "token");
+    assert!(!api.refresh_if_needed().await.unwrap());
+}
+
+#[tokio::test]
+async fn extra_login_parameters_are_sent_on_refresh() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let counter = logins.clone();
+    let base_url = common::serve(move |req| match req.path.as_str() {
+        common::LOGIN_PATH => {
+            let form = String::from_utf8_lossy(&req.body);
+            if form != "UserName=user&Password=password&Scope=data&Code=123+456" {
+                return Response::new(400);
+            }
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Response::json(EXPIRED_TOKEN),
+                _ => Response::json(common::TOKEN),
+            }
+        }
+        _ => Response::json("[]"),
+    })
+    .await;
+
+    let login = LoginRequest::new("user".into(), "password".into())
+        .param("Scope", "data")
+        .param("Code", "123 456");
+    assert!(!format!("{:?}", login).contains("123"));
+
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .build_with_login(login)
+        .await
+        .unwrap();
+    assert!(api.refresh_if_needed().await.unwrap());
+    assert_eq!(logins.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn requests_refresh_expired_tokens() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let api = common::client(&serve_expiring(logins.clone()).await).await;
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(logins.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn closed_session_clears_token_and_logs_in_again() {
+    let logins = Arc::new(AtomicUsize::new(1));
+    let api = common::client(&serve_expiring(logins.clone()).await).await;
+
+    let session = api.session();
+    assert!(session.get_user_files().await.unwrap().is_empty());
+    session.close().await;
+
+    assert!(api.token().unwrap().access_token.is_empty());
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(logins.load(Ordering::SeqCst), 3);
+}
+
+#[derive(Debug, Clone, Default)]
+struct FakeClock(Arc<AtomicI64>);
+
+impl Clock for FakeClock {
+    fn now_utc(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::from_unix_timestamp(self.0.load(Ordering::SeqCst)).unwrap()
+    }
+}
+
+#[tokio::test]
+async fn refresh_follows_injected_clock() {
+    let logins = Arc::new(AtomicUsize::new(1));
+    let base_url = serve_expiring(logins.clone()).await;
+    let clock = FakeClock::default();
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .clock(clock.clone())
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(logins.load(Ordering::SeqCst), 2);
+
+    // The token lasts an hour, and is refreshed a minute before it expires.
+    clock.0.store(3600 - 61, Ordering::SeqCst);
+    assert!(!api.refresh_if_needed().await.unwrap());
+
+    clock.0.store(3600 - 59, Ordering::SeqCst);
+    assert!(api.get_user_files().await.unwrap().is_empty());
+    assert_eq!(logins.load(Ordering::SeqCst), 3);
+}
+
+/// Client whose token has expired, with the next `failures` logins failing with a 503.
+async fn flaky_login_client(logins: Arc<AtomicUsize>, failures: usize) -> Lastkajen {
+    let base_url = common::serve(move |req| match req.path.as_str() {
+        common::LOGIN_PATH => match logins.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::json(EXPIRED_TOKEN),
+            n if n <= failures => Response::new(503),
+            _ => Response::json(common::TOKEN),
+        },
+        _ => Response::json("[]"),
+    })
+    .await;
+
+    Lastkajen::builder()
+        .base_url(base_url)
+        .backoff(Duration::from_millis(1))
+        .login_retries(2)
+        .build("user".into(), "password".into())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn transient_login_failures_are_retried() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let api = flaky_login_client(logins.clone(), 2).await;
+
+    assert!(api.refresh_if_needed().await.unwrap());
+    assert_eq!(logins.load(Ordering::SeqCst), 4);
+    assert_eq!(api.token().unwrap().access_token, "token");
+}
+
+#[tokio::test]
+async fn concurrent_callers_share_a_failed_refresh() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let api = flaky_login_client(logins.clone(), usize::MAX).await;
+
+    let results = futures::future::join_all((0..5).map(|_| api.get_user_files())).await;
+
+    for result in results {
+        assert!(matches!(
+            result,
+            Err(LastkajenError::RefreshFailed(err))
+                if matches!(*err, LastkajenError::ApiError(status, _) if status == 503)
+        ));
+    }
+    // The initial login, then one attempt and two retries.
+    assert_eq!(logins.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn paused_refresh_task_waits_for_resume() {
+    let logins = Arc::new(AtomicUsize::new(0));
+    let api = Arc::new(common::client(&serve_expiring(logins.clone()).await).await);
+
+    let handle = api.spawn_refresh_task();
+    handle.pause();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(logins.load(Ordering::SeqCst), 1);
+
+    handle.resume();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(logins.load(Ordering::SeqCst), 2);
+
+    handle.abort();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(handle.is_finished());
+}
+
+#[tokio::test]
+async fn clock_skew_comes_from_login_date() {
+    let base_url = common::serve(|req| match req.path.as_str() {
+        common::LOGIN_PATH => {
+            Response::json(common::TOKEN).header("Date", "Mon, 20 Nov 2023 10:15:00 GMT")
+        }
+        _ => Response::json("[]"),
+    })
+    .await;
+    // Local clock at 10:00:00 UTC that day.
+    let clock = FakeClock(Arc::new(AtomicI64::new(1_700_474_400)));
+    let api = Lastkajen::builder()
+        .base_url(base_url)
+        .clock(clock.clone())
+        .build("user".into(), "password".into())
+        .await
+        .unwrap();
+
+    assert_eq!(api.clock_skew(), Some(time::Duration::minutes(15)));
+    assert_eq!(
+        api.expiry_date_time(),
+        Some(clock.now_utc() + time::Duration::hours(1))
+    );
+
+    let api = common::client(&serve_expiring(Arc::default()).await).await;
+    assert_eq!(api.clock_skew(), None);
+}
@@ -0,0 +1,131 @@
+#![cfg(feature = "simd")]
+
+mod common;
+
+use std::fmt::Debug;
+
+use common::Response;
+use lastkajen::types::{DataPackageFile, DataPackageFolder, UserFile};
+use serde::de::DeserializeOwned;
+
+/// Published packages in both casings, with escaped backslashes in their source folders.
+const CATALOG: &str = r#"[
+    {
+        "id": 1,
+        "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler NVDB-data" },
+        "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+        "name": "Gävleborgs län",
+        "description": "Data från \"NVDB\"\nför Gävleborgs län",
+        "published": true
+    },
+    {
+        "Id": 3,
+        "TargetFolder": { "Id": 2, "Name": "Länsfiler", "Path": "Länsfiler NVDB-data" },
+        "SourceFolder": "Datapaket\\Länsfiler NVDB-data\\Uppsala län",
+        "Name": "Uppsala län",
+        "Description": "",
+        "Published": false
+    }
+]"#;
+
+/// A package's files, with sizes and date times needing the parsers' fallbacks.
+const PACKAGE_FILES: &str = r#"[
+    {
+        "isFolder": false,
+        "name": "Gävleborgs_län_GeoPackage.zip",
+        "size": "1,5 GB",
+        "dateTime": "2023-11-20T10:15:00+01:00",
+        "links": [
+            { "href": "/api/file/{id}{?fileName}", "rel": "download", "method": "GET", "isTemplated": true }
+        ]
+    },
+    {
+        "IsFolder": false,
+        "Name": "Gävleborgs_län_Shape.zip",
+        "Size": "n/a",
+        "DateTime": "2023-07-01T08:00:00",
+        "Links": []
+    }
+]"#;
+
+const USER_FILES: &str = r#"[
+    { "isFolder": false, "name": "order.zip", "size": "12 MB", "dateTime": "2023-11-20T10:15:00" },
+    { "IsFolder": true, "Name": "Äldre beställningar", "Size": "", "DateTime": "2023-01-02T03:04:05" }
+]"#;
+
+/// A listing where the second element can't be deserialized as a package.
+const LENIENT: &str = r#"[
+    {
+        "id": 1,
+        "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+        "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+        "name": "Gävleborgs län",
+        "description": "",
+        "published": true
+    },
+    { "id": "two", "name": null, "extra": [1.5, -2e3, {}] }
+]"#;
+
+/// Parse `json` with both parsers, which must agree.
+fn assert_parsers_agree<T: DeserializeOwned + PartialEq + Debug>(json: &str) -> T {
+    let serde: T = serde_json::from_str(json).unwrap();
+    let simd: T = simd_json::serde::from_slice(&mut json.as_bytes().to_vec()).unwrap();
+    assert_eq!(simd, serde);
+
+    serde
+}
+
+#[test]
+fn simd_json_parses_listings_like_serde_json() {
+    let packages: Vec<DataPackageFolder> = assert_parsers_agree(CATALOG);
+    assert_eq!(packages[1].id, 3);
+    let files: Vec<DataPackageFile> = assert_parsers_agree(PACKAGE_FILES);
+    assert_eq!(files[1].size_bytes(), None);
+    let user_files: Vec<UserFile> = assert_parsers_agree(USER_FILES);
+    assert!(user_files[1].is_folder);
+    let values: Vec<serde_json::Value> = assert_parsers_agree(LENIENT);
+    assert_eq!(values.len(), 2);
+}
+
+#[tokio::test]
+async fn simd_parsed_responses_match_serde_json() {
+    let base_url = common::serve_api(|req| {
+        let path = req.path.as_str();
+        if path.starts_with("/api/DataPackage/GetPublishedDataPackages") {
+            Response::json(CATALOG)
+        } else if path.starts_with("/api/DataPackage/GetDataPackageFiles") {
+            Response::json(PACKAGE_FILES)
+        } else {
+            Response::json(USER_FILES)
+        }
+    })
+    .await;
+    let api = common::client(&base_url).await;
+
+    let packages = api.get_published_packages().await.unwrap();
+    assert_eq!(
+        packages,
+        serde_json::from_str::<Vec<DataPackageFolder>>(CATALOG).unwrap()
+    );
+    let files = api.get_package_files_from_id(&1).await.unwrap();
+    assert_eq!(
+        files,
+        serde_json::from_str::<Vec<DataPackageFile>>(PACKAGE_FILES).unwrap()
+    );
+    let user_files = api.get_user_files().await.unwrap();
+    assert_eq!(
+        user_files,
+        serde_json::from_str::<Vec<UserFile>>(USER_FILES).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn simd_parsed_lenient_listing_skips_the_same_elements() {
+    let base_url = common::serve_api(|_| Response::json(LENIENT)).await;
+    let api = common::client(&base_url).await;
+
+    let (packages, issues) = api.get_published_packages_lenient().await.unwrap();
+    assert_eq!(packages.len(), 1);
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].index, 1);
+}
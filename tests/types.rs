@@ -0,0 +1,352 @@
+use lastkajen::types::{
+    County, DataPackageFile, DataPackageFolder, FileFormat, FileLink, FileSize, UserFile,
+};
+
+#[test]
+fn packages_deserialize_from_both_casings() {
+    let camel: DataPackageFolder = serde_json::from_str(
+        r#"{
+            "id": 1,
+            "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+            "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+            "name": "Gävleborgs län",
+            "description": "",
+            "published": true
+        }"#,
+    )
+    .unwrap();
+    let pascal: DataPackageFolder = serde_json::from_str(
+        r#"{
+            "Id": 1,
+            "TargetFolder": { "Id": 2, "Name": "Länsfiler", "Path": "Länsfiler" },
+            "SourceFolder": "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+            "Name": "Gävleborgs län",
+            "Description": "",
+            "Published": true
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(camel, pascal);
+}
+
+#[test]
+fn package_files_deserialize_from_both_casings() {
+    let camel: DataPackageFile = serde_json::from_str(
+        r#"{
+            "isFolder": false,
+            "name": "Gävleborgs_län_GeoPackage.zip",
+            "size": "1,5 GB",
+            "dateTime": "2023-11-20T10:15:00+01:00",
+            "links": [{ "href": "/file", "rel": "download", "method": "GET", "isTemplated": false }]
+        }"#,
+    )
+    .unwrap();
+    let pascal: DataPackageFile = serde_json::from_str(
+        r#"{
+            "IsFolder": false,
+            "Name": "Gävleborgs_län_GeoPackage.zip",
+            "Size": "1,5 GB",
+            "DateTime": "2023-11-20T10:15:00+01:00",
+            "Links": [{ "Href": "/file", "Rel": "download", "Method": "GET", "IsTemplated": false }]
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(camel, pascal);
+}
+
+#[test]
+fn user_files_deserialize_from_both_casings() {
+    let camel: UserFile = serde_json::from_str(
+        r#"{ "isFolder": false, "name": "order.zip", "size": "12 MB", "dateTime": "2023-11-20T10:15:00" }"#,
+    )
+    .unwrap();
+    let pascal: UserFile = serde_json::from_str(
+        r#"{ "IsFolder": false, "Name": "order.zip", "Size": "12 MB", "DateTime": "2023-11-20T10:15:00" }"#,
+    )
+    .unwrap();
+
+    assert_eq!(camel, pascal);
+}
+
+#[test]
+fn county_name_is_taken_from_source_folder() {
+    let folders = [
+        (
+            "Datapaket\\Länsfiler NVDB-data\\Gävleborgs län",
+            Some("Gävleborgs län"),
+        ),
+        (
+            "Datapaket\\Länsfiler NVDB-data\\Uppsala län\\Vägnät",
+            Some("Uppsala län"),
+        ),
+        ("Datapaket\\Länsfiler NVDB-data\\Hela landet", None),
+        ("Datapaket\\Riksfiler NVDB-data", None),
+        ("", None),
+    ];
+
+    for (source_folder, county) in folders {
+        let package: DataPackageFolder = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+            "sourceFolder": source_folder,
+            "name": "Paket",
+            "description": "",
+            "published": true,
+        }))
+        .unwrap();
+
+        assert_eq!(package.county_name(), county, "{}", source_folder);
+    }
+}
+
+#[test]
+fn sizes_accept_either_decimal_separator() {
+    assert_eq!(FileSize::parse("1,5 GB").bytes(), Some(3 << 29));
+    assert_eq!(FileSize::parse("1.5 GB").bytes(), Some(3 << 29));
+    assert_eq!(FileSize::parse("1.024,5 KB").bytes(), Some(1_049_088));
+    assert_eq!(FileSize::parse("1,024.5 KB").bytes(), Some(1_049_088));
+    assert_eq!(FileSize::parse("1,048,576 B").bytes(), Some(1 << 20));
+}
+
+#[test]
+fn ambiguous_size_separator_is_decimal() {
+    // A lone separator is read as decimal, the way the API formats sizes.
+    let size = FileSize::parse("1,234 MB");
+    assert_eq!(
+        size.bytes(),
+        Some((1.234 * (1 << 20) as f64).round() as u64)
+    );
+
+    assert_eq!(size.bytes_with_locale('.'), Some(1234 << 20));
+    assert_eq!(size.bytes_with_locale(','), size.bytes());
+}
+
+#[cfg(feature = "time")]
+#[test]
+fn date_times_without_offset_are_swedish_local_time() {
+    let file = |date_time: &str| -> DataPackageFile {
+        serde_json::from_value(serde_json::json!({
+            "isFolder": false,
+            "name": "file.zip",
+            "size": "1 MB",
+            "dateTime": date_time,
+            "links": [],
+        }))
+        .unwrap()
+    };
+
+    let winter = file("2023-11-20T10:15:00");
+    assert_eq!(
+        winter.date_time,
+        file("2023-11-20T10:15:00+01:00").date_time
+    );
+
+    let summer = file("2023-07-01T10:15:00");
+    assert_eq!(
+        summer.date_time,
+        file("2023-07-01T10:15:00+02:00").date_time
+    );
+}
+
+#[test]
+fn formatted_sizes_parse_back() {
+    for bytes in [0, 1023, 1024, 1_048_575, 5_368_709_120, 3 << 40] {
+        let formatted = lastkajen::util::format_bytes(bytes);
+        let parsed = FileSize::parse(&formatted).bytes().unwrap();
+
+        // Only one decimal is kept, so a round-trip can be off by up to 5%.
+        assert!(
+            parsed.abs_diff(bytes) <= bytes / 20,
+            "{} formatted as {:?}",
+            bytes,
+            formatted
+        );
+    }
+    assert_eq!(lastkajen::util::format_bytes(1_048_575), "1.0 MB");
+}
+
+#[test]
+fn counties_round_trip_through_their_names() {
+    for county in County::ALL {
+        assert_eq!(county.to_string().parse(), Ok(county.clone()));
+        assert_eq!(county.short_name().parse(), Ok(county));
+    }
+    assert_eq!(
+        "Ålands län".parse(),
+        Ok(County::Other("Ålands län".to_string()))
+    );
+
+    let package: DataPackageFolder = serde_json::from_value(serde_json::json!({
+        "id": 1,
+        "targetFolder": { "id": 2, "name": "Länsfiler", "path": "Länsfiler" },
+        "sourceFolder": "Datapaket\\Länsfiler NVDB-data\\Jönköpings län",
+        "name": "Jönköpings län",
+        "description": "",
+        "published": true,
+    }))
+    .unwrap();
+    assert_eq!(package.county(), Some(County::Jonkoping));
+}
+
+#[test]
+fn file_formats_are_detected_from_names() {
+    let formats = [
+        ("Gävleborgs_län_GeoPackage.zip", FileFormat::GeoPackage),
+        ("Gävleborgs_län_Shape.zip", FileFormat::Shape),
+        ("Vägnät_INSPIRE.zip", FileFormat::InspireGml),
+        ("hastighet.csv", FileFormat::Csv),
+        ("Gävleborgs_län.zip", FileFormat::Unknown),
+        ("Vägnät GML-format.zip", FileFormat::InspireGml),
+        ("ESRI_Shapefile.zip", FileFormat::Shape),
+        // Keywords inside other words don't count.
+        ("Broar_Shapeinfo.zip", FileFormat::Unknown),
+        ("Gpkgexport_Csvfri.zip", FileFormat::Unknown),
+    ];
+
+    for (name, format) in formats {
+        let file: DataPackageFile = serde_json::from_value(serde_json::json!({
+            "isFolder": false,
+            "name": name,
+            "size": "1 MB",
+            "dateTime": "2023-11-20T10:15:00+01:00",
+            "links": [],
+        }))
+        .unwrap();
+        assert_eq!(file.format(), format, "{}", name);
+    }
+    assert_eq!(
+        FileFormat::InspireGml.to_string().parse(),
+        Ok(FileFormat::InspireGml)
+    );
+}
+
+#[test]
+fn formats_are_detected_from_magic_bytes() {
+    use lastkajen::util::{detect_format, DetectedFormat};
+
+    let cases: [(&[u8], DetectedFormat); 7] = [
+        (b"PK\x05\x06", DetectedFormat::Zip),
+        (b"SQLite format 3\0\x10\x00", DetectedFormat::GeoPackage),
+        (
+            "\u{feff}<?xml version=\"1.0\"?><gml:FeatureCollection>".as_bytes(),
+            DetectedFormat::Xml,
+        ),
+        (b"\n  <HTML><head>", DetectedFormat::Html),
+        ("väg,längd\n".as_bytes(), DetectedFormat::Csv),
+        // Cut off in the middle of the "ä".
+        (&"väg;längd".as_bytes()[..6], DetectedFormat::Csv),
+        (b"\x00\x01\x02", DetectedFormat::Unknown),
+    ];
+
+    for (bytes, format) in cases {
+        assert_eq!(detect_format(bytes), format, "{:?}", bytes);
+    }
+}
+
+#[test]
+fn link_hrefs_are_made_absolute() {
+    let link = |href: &str| FileLink {
+        href: href.into(),
+        rel: "download".into(),
+        method: "GET".into(),
+        is_templated: false,
+    };
+    let base = "https://lastkajen.trafikverket.se";
+
+    assert_eq!(
+        link("/api/file/GetFileStream?token=x").absolute_url(base),
+        "https://lastkajen.trafikverket.se/api/file/GetFileStream?token=x"
+    );
+    assert_eq!(
+        link("file.zip").absolute_url("https://example.com/api/files/"),
+        "https://example.com/api/files/file.zip"
+    );
+    assert_eq!(
+        link("https://cdn.example.com/file.zip").absolute_url(base),
+        "https://cdn.example.com/file.zip"
+    );
+    assert_eq!(link("/file.zip").absolute_url("not a url"), "/file.zip");
+}
+
+#[test]
+fn templated_hrefs_expand_every_operator() {
+    let link = |href: &str| FileLink {
+        href: href.into(),
+        rel: "download".into(),
+        method: "GET".into(),
+        is_templated: true,
+    };
+    let params = std::collections::HashMap::from([
+        ("folder", "Länsfiler/Gävle".to_string()),
+        ("name", "file".to_string()),
+        ("ext", "zip".to_string()),
+        ("empty", String::new()),
+    ]);
+
+    let cases = [
+        ("{folder}", "L%C3%A4nsfiler%2FG%C3%A4vle"),
+        ("{+folder}", "L%C3%A4nsfiler/G%C3%A4vle"),
+        ("{#name}", "#file"),
+        ("/files/{name}{.ext}", "/files/file.zip"),
+        ("/api{/name,ext}", "/api/file/zip"),
+        ("/api{;name,empty}", "/api;name=file;empty"),
+        ("/api{?name,empty}", "/api?name=file&empty="),
+        ("/api?a=1{&name}", "/api?a=1&name=file"),
+        ("/api{/missing}{.missing}{;missing}", "/api"),
+    ];
+
+    for (href, resolved) in cases {
+        assert_eq!(link(href).resolve(&params).unwrap(), resolved, "{}", href);
+    }
+}
+
+#[test]
+fn reserved_template_operators_fail() {
+    let params = std::collections::HashMap::from([("name", "file".to_string())]);
+
+    for operator in ['=', ',', '!', '@', '|'] {
+        let link = FileLink {
+            href: format!("/api/{{{}name}}", operator),
+            rel: "download".into(),
+            method: "GET".into(),
+            is_templated: true,
+        };
+        assert!(link.resolve(&params).is_err(), "{}", link.href);
+    }
+}
+
+#[test]
+fn download_links_are_found_by_rel() {
+    let file = |is_folder: bool, rels: &[&str]| -> DataPackageFile {
+        let links: Vec<_> = rels
+            .iter()
+            .map(|rel| {
+                serde_json::json!({
+                    "href": format!("/{}", rel),
+                    "rel": rel,
+                    "method": "GET",
+                    "isTemplated": false,
+                })
+            })
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "isFolder": is_folder,
+            "name": "file.zip",
+            "size": "1 MB",
+            "dateTime": "2023-11-20T10:15:00+01:00",
+            "links": links,
+        }))
+        .unwrap()
+    };
+
+    let downloadable = file(false, &["self", "FileDownload"]);
+    assert_eq!(downloadable.download_link().unwrap().href, "/FileDownload");
+    assert!(downloadable.is_downloadable());
+
+    assert_eq!(file(false, &["self"]).download_link(), None);
+    assert!(!file(false, &["self"]).is_downloadable());
+    assert!(!file(false, &[]).is_downloadable());
+    assert!(!file(true, &["download"]).is_downloadable());
+}